@@ -2,10 +2,32 @@ use std::os::unix::process::CommandExt;
 use std::process;
 use anyhow::{anyhow, Result};
 use std::env;
+use crate::execute::{Executor, ControlFlow, exit_status, status_code};
+use std::process::ExitStatus;
+use crate::execute::jobs::JobState;
+use crate::execute::{signals, exit_shell};
+use crate::execute::traps::{Trap, Action};
+use crate::expand::{self, arith, bytes};
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use nix::sys::signal::Signal;
+use nix::unistd::Pid;
+use nix::errno::Errno;
 
-pub fn cd(args: &[String]) -> Result<()> {
-    let p = std::path::Path::new(&args[1]);
-    env::set_current_dir(p)?;
+/// Changes directory, to `$HOME` if no directory is given and to the
+/// previous one with `cd -`. `PWD` and `OLDPWD` follow along.
+pub fn cd(shell: &mut Executor, args: &[OsString]) -> Result<()> {
+    let previous = args.get(1).is_some_and(|arg| arg == "-");
+    let dir = match args.get(1) {
+        None                => shell.variables.get("HOME").ok_or_else(|| anyhow!("cd: HOME not set"))?,
+        Some(_) if previous => shell.variables.get("OLDPWD").ok_or_else(|| anyhow!("cd: OLDPWD not set"))?,
+        Some(dir)           => dir.as_os_str(),
+    }.to_os_string();
+    let old = match shell.variables.get("PWD") {
+        Some(pwd) => pwd.to_os_string(),
+        None      => env::current_dir()?.into_os_string(),
+    };
+    env::set_current_dir(&dir).map_err(|e| anyhow!("cd: {}: {}", dir.display(), e))?;
 
     //MacOs uses symlinks for /var, /tmp, and /etc
     //and redirects them to /private/var, /private/tmp, and /private/etc
@@ -13,30 +35,521 @@ pub fn cd(args: &[String]) -> Result<()> {
     if env::current_dir()? == std::path::PathBuf::from("/private") {
         env::set_current_dir("/")?;
     }
+    let pwd = env::current_dir()?.into_os_string();
+    if previous {
+        bytes::println(&pwd)?;
+    }
+    shell.variables.set("OLDPWD", old);
+    shell.variables.set("PWD", pwd);
     Ok(())
 }
 
 /// On success, this function does not return but instead swaps
 /// the current process with the called process. Similar to exit,
 /// no stack cleanup will be performed or destructors called.
-pub fn exec(args: &[String]) -> Result<()> {
-    let e = process::Command::new(&args[1])
-        .args(&args[2..])
-        .exec();
+pub fn exec(args: &[OsString]) -> Result<()> {
+    if args.len() < 2 {
+        return Ok(());
+    }
+    let mut command = process::Command::new(&args[1]);
+    command.args(&args[2..]);
+    //runs in this process just before exec, so a failed exec keeps them
+    unsafe {
+        command.pre_exec(|| {
+            signals::reset();
+            Ok(())
+        });
+    }
+    let e = command.exec();
     Err(anyhow!(e))
 }
 
 pub fn pwd() -> Result<()> {
-    println!("{}", std::env::current_dir()?
-             .as_path()
-             .display());
+    bytes::println(env::current_dir()?.as_os_str())?;
     Ok(())
 }
 
-pub fn exit(args: &[String]) -> Result<()> {
-    let mut code = 0;
+pub fn exit(shell: &mut Executor, args: &[String]) -> Result<()> {
+    let mut code = status_code(&shell.last_status);
     if args.len() > 1 {
         code = args[1].parse::<i32>()?;
     }
-    process::exit(code);
+    exit_shell(shell, code);
+}
+
+/// Marks variables for export, assigning them first when given as `NAME=value`
+pub fn export(shell: &mut Executor, args: &[OsString]) -> Result<()> {
+    if args.len() == 1 {
+        for (name, var) in shell.variables.iter().filter(|(_, var)| var.exported) {
+            println!("export {}=\"{}\"", name, var.value.display());
+        }
+    }
+    for arg in &args[1..] {
+        let (name, value) = split_assignment(arg);
+        if let Some(value) = value {
+            shell.variables.set(&name, value);
+        }
+        shell.variables.export(&name);
+    }
+    Ok(())
+}
+
+/// Splits a `name=value` argument at its first `=`, keeping the value's
+/// bytes as they are
+fn split_assignment(arg: &OsStr) -> (String, Option<OsString>) {
+    let arg = arg.as_bytes();
+    match arg.iter().position(|byte| *byte == b'=') {
+        Some(i) => (String::from_utf8_lossy(&arg[..i]).into_owned(), Some(OsString::from_vec(arg[i + 1..].to_vec()))),
+        None    => (String::from_utf8_lossy(arg).into_owned(), None),
+    }
+}
+
+pub fn unset(shell: &mut Executor, args: &[String]) -> Result<()> {
+    for name in &args[1..] {
+        shell.variables.unset(name);
+    }
+    Ok(())
+}
+
+pub fn shift(shell: &mut Executor, args: &[String]) -> Result<()> {
+    let count = match args.get(1) {
+        Some(count) => count.parse::<usize>()?,
+        None        => 1,
+    };
+    if count > shell.positional.len() {
+        return Err(anyhow!("shift: shift count out of range"));
+    }
+    shell.positional.drain(..count);
+    Ok(())
+}
+
+/// With no arguments lists every variable, otherwise replaces the
+/// positional parameters with the arguments following `--`
+pub fn set(shell: &mut Executor, args: &[OsString]) -> Result<()> {
+    if args.len() == 1 {
+        for (name, var) in shell.variables.iter() {
+            println!("{}=\"{}\"", name, var.value.display());
+        }
+        return Ok(());
+    }
+    //options come first, and `-x` turns one on where `+x` turns it off
+    let mut args = &args[1..];
+    while let Some(arg) = args.first() {
+        if arg == "--" {
+            shell.positional = args[1..].to_vec();
+            return Ok(());
+        }
+        let Some(arg) = arg.to_str() else {
+            break;
+        };
+        let on = arg.starts_with('-');
+        if !(on || arg.starts_with('+')) || arg.len() == 1 {
+            break;
+        }
+        let letters = &arg[1..];
+        args = &args[1..];
+        for letter in letters.chars() {
+            if letter != 'o' {
+                shell.options.set_letter(letter, on)?;
+                continue;
+            }
+            match args.first() {
+                Some(name) => {
+                    shell.options.set(&name.to_string_lossy(), on)?;
+                    args = &args[1..];
+                },
+                None if on => shell.options.print(),
+                None       => shell.options.print_commands(),
+            }
+        }
+    }
+    if !args.is_empty() {
+        shell.positional = args.to_vec();
+    }
+    Ok(())
+}
+
+pub fn break_loop(shell: &mut Executor, args: &[String]) -> Result<()> {
+    let count = loop_count(shell, args, "break")?;
+    shell.control_flow = Some(ControlFlow::Break(count));
+    Ok(())
+}
+
+pub fn continue_loop(shell: &mut Executor, args: &[String]) -> Result<()> {
+    let count = loop_count(shell, args, "continue")?;
+    shell.control_flow = Some(ControlFlow::Continue(count));
+    Ok(())
+}
+
+/// How many enclosing loops `break N` or `continue N` applies to,
+/// capped at the number of loops actually running
+fn loop_count(shell: &Executor, args: &[String], name: &str) -> Result<u32> {
+    if shell.loop_depth == 0 {
+        return Err(anyhow!("{}: only meaningful in a loop", name));
+    }
+    let count = match args.get(1) {
+        Some(count) => count.parse::<u32>()?,
+        None        => 1,
+    };
+    if count == 0 {
+        return Err(anyhow!("{}: {}: loop count out of range", name, count));
+    }
+    Ok(count.min(shell.loop_depth))
+}
+
+pub fn return_function(shell: &mut Executor, args: &[String]) -> Result<()> {
+    if shell.function_depth == 0 {
+        return Err(anyhow!("return: can only `return' from a function"));
+    }
+    let code = match args.get(1) {
+        Some(code) => code.parse::<i32>().map_err(|_| anyhow!("return: {}: numeric argument required", code))?,
+        None       => status_code(&shell.last_status),
+    };
+    shell.control_flow = Some(ControlFlow::Return(code));
+    Ok(())
+}
+
+/// Evaluates each argument as an arithmetic expression. The status is 0
+/// if the last one isn't zero, and 1 if it is.
+pub fn let_arithmetic(shell: &mut Executor, args: &[String]) -> Result<ExitStatus> {
+    if args.len() < 2 {
+        return Err(anyhow!("let: expression expected"));
+    }
+    let mut value = 0;
+    for arg in &args[1..] {
+        let expr = arith::parse(arg).map_err(|e| anyhow!("let: {}", e))?;
+        value = arith::evaluate(shell, &expr).map_err(|e| anyhow!("let: {}: {}", arg, e))?;
+    }
+    Ok(exit_status((value == 0) as i32))
+}
+
+/// Makes each named variable local to the running function, optionally
+/// assigning it with `name=value`
+pub fn local(shell: &mut Executor, args: &[OsString]) -> Result<()> {
+    if shell.function_depth == 0 {
+        return Err(anyhow!("local: can only be used in a function"));
+    }
+    for arg in &args[1..] {
+        let (name, value) = split_assignment(arg);
+        shell.variables.make_local(&name);
+        if let Some(value) = value {
+            shell.variables.set(&name, value);
+        }
+    }
+    Ok(())
+}
+
+/// Reads a line from stdin and splits it on IFS into the named variables,
+/// the last one taking whatever is left over. With no names the whole
+/// line goes in `REPLY`. Unless `-r` is given, a backslash escapes the
+/// next character and a backslash before the newline continues the line.
+/// The status is 1 at end of input.
+pub fn read(shell: &mut Executor, args: &[String]) -> Result<ExitStatus> {
+    let mut raw = false;
+    let mut names = vec![];
+    for arg in &args[1..] {
+        match arg.as_str() {
+            "-r" => raw = true,
+            name if name.starts_with('-') => return Err(anyhow!("read: {}: invalid option", name)),
+            name => names.push(name),
+        }
+    }
+    let (line, eof) = read_line(raw)?;
+    if names.is_empty() {
+        shell.variables.set("REPLY", OsString::from_vec(line));
+    }
+    else {
+        let ifs = shell.variables.get("IFS").map_or(expand::DEFAULT_IFS.into(), OsStr::to_os_string);
+        let mut fields = split_read_fields(&line, ifs.as_bytes(), names.len()).into_iter();
+        for name in names {
+            shell.variables.set(name, fields.next().unwrap_or_default());
+        }
+    }
+    Ok(exit_status(eof as i32))
+}
+
+/// Reads stdin a byte at a time up to a newline, so nothing after the line
+/// is taken from anything else reading the same input. Returns the line
+/// and whether input ended before a newline.
+fn read_line(raw: bool) -> Result<(Vec<u8>, bool)> {
+    let mut line = vec![];
+    let mut escaped = false;
+    loop {
+        let mut byte = [0];
+        let read = match nix::unistd::read(0, &mut byte) {
+            Ok(read) => read,
+            Err(Errno::EINTR) => continue,
+            Err(e) => return Err(anyhow!("read: {}", e.desc())),
+        };
+        if read == 0 {
+            return Ok((line, true));
+        }
+        match byte[0] {
+            b'\n' if escaped => { escaped = false; },
+            b'\n' => return Ok((line, false)),
+            b'\\' if !raw && !escaped => { escaped = true; },
+            byte => {
+                escaped = false;
+                line.push(byte);
+            },
+        }
+    }
+}
+
+/// Splits a line read by `read` into at most `count` fields. IFS
+/// whitespace at either end is dropped, and the last field keeps the rest
+/// of the line, delimiters included, unless all that is left is a single
+/// field and its delimiter.
+fn split_read_fields(line: &[u8], ifs: &[u8], count: usize) -> Vec<OsString> {
+    let chars = bytes::chars(line);
+    let ends = bytes::boundaries(line);
+    let separators = bytes::chars(ifs);
+    let is_ifs = |i: usize| separators.iter().any(|(separator, _)| *separator == chars[i].0);
+    let is_space = |i: usize| is_ifs(i) && matches!(chars[i].1, Some(' ' | '\t' | '\n'));
+    let skip_spaces = |mut i: usize| {
+        while i < chars.len() && is_space(i) {
+            i += 1;
+        }
+        i
+    };
+    //a delimiter is whitespace around at most one other IFS character
+    let skip_delimiter = |i: usize| -> usize {
+        let i = skip_spaces(i);
+        match i < chars.len() && is_ifs(i) && !is_space(i) {
+            true  => skip_spaces(i + 1),
+            false => i,
+        }
+    };
+    let field_end = |mut i: usize, end: usize| {
+        while i < end && !is_ifs(i) {
+            i += 1;
+        }
+        i
+    };
+    let text = |start: usize, end: usize| OsStr::from_bytes(&line[ends[start]..ends[end]]).to_os_string();
+    let mut start = skip_spaces(0);
+    let mut fields = vec![];
+    while fields.len() + 1 < count && start < chars.len() {
+        let end = field_end(start, chars.len());
+        fields.push(text(start, end));
+        start = skip_delimiter(end);
+    }
+    let mut rest = chars.len();
+    while rest > start && is_space(rest - 1) {
+        rest -= 1;
+    }
+    let end = field_end(start, rest);
+    match skip_delimiter(end) >= rest {
+        true  => fields.push(text(start, end)),
+        false => fields.push(text(start, rest)),
+    }
+    fields
+}
+
+/// Waits for the given jobs, or for every job when none are given, and
+/// returns the status of the last one
+pub fn wait(shell: &mut Executor, args: &[String]) -> Result<ExitStatus> {
+    if args.len() == 1 {
+        //waiting for every job succeeds whatever their statuses
+        for id in shell.jobs.ids() {
+            shell.jobs.wait(id)?;
+        }
+        return Ok(exit_status(0));
+    }
+    let ids = args[1..].iter()
+        .map(|spec| shell.jobs.find(spec).map_err(|e| anyhow!("wait: {}", e)))
+        .collect::<Result<Vec<_>>>()?;
+    let mut status = exit_status(0);
+    for id in ids {
+        status = shell.jobs.wait(id)?;
+    }
+    Ok(status)
+}
+
+pub fn jobs(shell: &mut Executor) -> Result<()> {
+    shell.jobs.reap();
+    shell.jobs.list();
+    Ok(())
+}
+
+/// Continues a job in the foreground and waits for it
+pub fn fg(shell: &mut Executor, args: &[String]) -> Result<ExitStatus> {
+    if !shell.job_control {
+        return Err(anyhow!("fg: no job control"));
+    }
+    let id = shell.jobs.find_or_current(args).map_err(|e| anyhow!("fg: {}", e))?;
+    let job = shell.jobs.get_mut(id)?;
+    println!("{}", job.command);
+    job.resume()?;
+    job.wait_in_foreground()?;
+    let status = job.status();
+    if job.state() == JobState::Stopped {
+        shell.jobs.report_stopped(id);
+    }
+    else {
+        shell.jobs.remove(id);
+    }
+    Ok(status)
+}
+
+/// Continues stopped jobs in the background
+pub fn bg(shell: &mut Executor, args: &[String]) -> Result<()> {
+    if !shell.job_control {
+        return Err(anyhow!("bg: no job control"));
+    }
+    let ids = match args.len() {
+        1 => vec![shell.jobs.find_or_current(args).map_err(|e| anyhow!("bg: {}", e))?],
+        _ => args[1..].iter()
+                .map(|spec| shell.jobs.find(spec).map_err(|e| anyhow!("bg: {}", e)))
+                .collect::<Result<Vec<_>>>()?,
+    };
+    for id in ids {
+        let job = shell.jobs.get_mut(id)?;
+        if job.state() == JobState::Running {
+            return Err(anyhow!("bg: job {} already in background", id));
+        }
+        job.resume()?;
+        println!("[{}] {} &", id, job.command);
+    }
+    Ok(())
+}
+
+/// `kill [-s signal | -signal] target...`, where each target is a pid or
+/// a job spec. `kill -l` lists the signal names.
+pub fn kill(shell: &mut Executor, args: &[String]) -> Result<()> {
+    let mut signal = Signal::SIGTERM;
+    let mut targets = &args[1..];
+    match targets.first().map(String::as_str) {
+        Some("-l") => {
+            let names: Vec<&str> = Signal::iterator().map(|signal| &signal.as_str()[3..]).collect();
+            println!("{}", names.join(" "));
+            return Ok(());
+        },
+        Some("-s") => {
+            let name = targets.get(1).ok_or_else(|| anyhow!("kill: -s: option requires an argument"))?;
+            signal = parse_signal(name)?;
+            targets = &targets[2..];
+        },
+        Some("--") => targets = &targets[1..],
+        Some(arg) if arg.starts_with('-') => {
+            signal = parse_signal(&arg[1..])?;
+            targets = &targets[1..];
+        },
+        _ => {},
+    }
+    if targets.is_empty() {
+        return Err(anyhow!("kill: usage: kill [-s sigspec | -sigspec] pid | jobspec ..."));
+    }
+    for target in targets {
+        if target.starts_with('%') {
+            let id = shell.jobs.find(target).map_err(|e| anyhow!("kill: {}", e))?;
+            let job = shell.jobs.get_mut(id)?;
+            job.signal(signal)?;
+            //a stopped job has to run to act on most signals
+            if job.state() == JobState::Stopped && signal != Signal::SIGSTOP {
+                job.resume()?;
+            }
+        }
+        else {
+            let pid = target.parse::<i32>()
+                .map_err(|_| anyhow!("kill: {}: arguments must be process or job IDs", target))?;
+            nix::sys::signal::kill(Pid::from_raw(pid), signal).map_err(|e| anyhow!("kill: ({}) - {}", pid, e.desc()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Accepts a signal as a number or a name with or without its `SIG` prefix
+fn parse_signal(spec: &str) -> Result<Signal> {
+    let signal = match spec.parse::<i32>() {
+        Ok(number) => Signal::try_from(number).ok(),
+        Err(_) => {
+            let name = spec.to_uppercase();
+            let name = if name.starts_with("SIG") { name } else { format!("SIG{}", name) };
+            name.parse::<Signal>().ok()
+        },
+    };
+    signal.ok_or_else(|| anyhow!("kill: {}: invalid signal specification", spec))
+}
+
+/// Removes jobs from the job table, so the shell no longer tracks them
+pub fn disown(shell: &mut Executor, args: &[String]) -> Result<()> {
+    let ids = match args.get(1).map(String::as_str) {
+        Some("-a") => shell.jobs.ids(),
+        None       => vec![shell.jobs.find_or_current(args).map_err(|e| anyhow!("disown: {}", e))?],
+        Some(_)    => args[1..].iter()
+                        .map(|spec| shell.jobs.find(spec).map_err(|e| anyhow!("disown: {}", e)))
+                        .collect::<Result<Vec<_>>>()?,
+    };
+    for id in ids {
+        shell.jobs.remove(id);
+    }
+    Ok(())
+}
+
+/// `trap action condition...` sets traps, `trap - condition...` resets
+/// them, `trap '' condition...` ignores them and `trap` or `trap -p
+/// [condition...]` lists them
+pub fn trap(shell: &mut Executor, args: &[String]) -> Result<()> {
+    let mut args = &args[1..];
+    if args.first().is_some_and(|arg| arg == "--") {
+        args = &args[1..];
+    }
+    if args.is_empty() || args[0] == "-p" {
+        let traps = parse_traps(args.get(1..).unwrap_or_default())?;
+        shell.traps.print(&traps);
+        return Ok(());
+    }
+    //a lone condition, or a number where the action would be, resets
+    let reset = args.len() == 1 || args[0].parse::<u32>().is_ok();
+    let (action, conditions) = if reset {
+        (None, args)
+    }
+    else {
+        (Some(args[0].as_str()), &args[1..])
+    };
+    for trap in parse_traps(conditions)? {
+        match action {
+            None | Some("-") => shell.traps.reset(trap)?,
+            Some("")         => shell.traps.set(trap, Action::Ignore)?,
+            Some(command)    => shell.traps.set(trap, Action::Command(command.to_string()))?,
+        }
+    }
+    Ok(())
+}
+
+fn parse_traps(names: &[String]) -> Result<Vec<Trap>> {
+    names.iter()
+        .map(|name| name.parse::<Trap>().map_err(|e| anyhow!("trap: {}", e)))
+        .collect()
+}
+
+/// `shopt -s name...` and `shopt -u name...` turn options on and off, and
+/// `shopt [-p] [name...]` lists them
+pub fn shopt(shell: &mut Executor, args: &[String]) -> Result<()> {
+    let mut args = &args[1..];
+    let mut on = None;
+    let mut as_commands = false;
+    while let Some(arg) = args.first().filter(|arg| arg.starts_with('-')) {
+        match arg.as_str() {
+            "-s" => on = Some(true),
+            "-u" => on = Some(false),
+            "-p" => as_commands = true,
+            "--" => {
+                args = &args[1..];
+                break;
+            },
+            _ => return Err(anyhow!("shopt: {}: invalid option", arg)),
+        }
+        args = &args[1..];
+    }
+    match on {
+        Some(on) if !args.is_empty() => {
+            for name in args {
+                shell.options.shopt(name, on)?;
+            }
+            Ok(())
+        },
+        _ => shell.options.print_shopt(args, as_commands),
+    }
 }
@@ -0,0 +1,220 @@
+use crate::execute::{Execute, Pipe, Executor, ControlFlow, execute_list, execute_condition, execute_or_report, exit_status, fork_subshell};
+use crate::expand;
+use crate::expand::pattern::Pattern;
+use crate::command::{TopLevelWordAlias, RedirectAlias};
+use super::redirect::{convert_redirects, execute_redirected};
+use std::process::ExitStatus;
+use std::ffi::OsString;
+use std::io::{PipeReader, PipeWriter};
+use std::os::fd::OwnedFd;
+use nix::unistd::Pid;
+use anyhow::Result;
+
+type BoxedExecutable = Box<dyn Execute>;
+
+/// `if`, with each `elif` as another guard and body pair
+pub struct IfCommand {
+    pub(super) branches: Vec<(Vec<BoxedExecutable>, Vec<BoxedExecutable>)>,
+    pub(super) else_branch: Option<Vec<BoxedExecutable>>,
+}
+
+/// `while` and `until` loops, which differ only in how the guard is tested
+pub struct LoopCommand {
+    pub(super) guard: Vec<BoxedExecutable>,
+    pub(super) body: Vec<BoxedExecutable>,
+    pub(super) until: bool,
+}
+
+/// `for var in words`, or over the positional parameters when there
+/// are no words
+pub struct ForCommand {
+    pub(super) var: String,
+    pub(super) words: Option<Vec<TopLevelWordAlias>>,
+    pub(super) body: Vec<BoxedExecutable>,
+}
+
+/// `{ list; }`, which runs the list in the current shell
+pub struct BraceGroup {
+    pub(super) commands: Vec<BoxedExecutable>,
+}
+
+/// A compound command with redirects after it, as in `{ a; b; } > log` or
+/// `while read line; do ...; done < file`
+pub struct RedirectedCompound {
+    pub(super) command: BoxedExecutable,
+    pub(super) redirects: Vec<RedirectAlias>,
+}
+
+/// A compound command as a stage of a pipeline, which runs in a subshell
+/// with the stage's pipes as its stdin and stdout
+pub struct CompoundStage {
+    pub(super) command: BoxedExecutable,
+    pub(super) stdin: Option<OwnedFd>,
+    pub(super) stdout: Option<OwnedFd>,
+}
+
+/// What a loop should do after its body has run once
+enum Iteration {
+    Next,
+    Stop,
+}
+
+/// Consumes a `break` or `continue` aimed at this loop, passing on any
+/// that are meant for an outer loop
+fn end_iteration(shell: &mut Executor) -> Iteration {
+    match shell.control_flow.take() {
+        None                        => Iteration::Next,
+        Some(ControlFlow::Break(1)) => Iteration::Stop,
+        Some(ControlFlow::Continue(1)) => Iteration::Next,
+        Some(ControlFlow::Break(n)) => {
+            shell.control_flow = Some(ControlFlow::Break(n - 1));
+            Iteration::Stop
+        },
+        Some(ControlFlow::Continue(n)) => {
+            shell.control_flow = Some(ControlFlow::Continue(n - 1));
+            Iteration::Stop
+        },
+        //`return` unwinds every loop in the function, and an interrupt
+        //every loop there is
+        Some(control_flow @ (ControlFlow::Return(_) | ControlFlow::Interrupt)) => {
+            shell.control_flow = Some(control_flow);
+            Iteration::Stop
+        },
+    }
+}
+
+impl Execute for IfCommand {
+    fn execute(&mut self, shell: &mut Executor) -> Result<ExitStatus> {
+        for (guard, body) in &mut self.branches {
+            let guard_status = execute_condition(guard, shell);
+            if shell.control_flow.is_some() {
+                return Ok(guard_status);
+            }
+            if guard_status.success() {
+                return Ok(execute_list(body, shell));
+            }
+        }
+        match &mut self.else_branch {
+            Some(body) => Ok(execute_list(body, shell)),
+            None       => Ok(exit_status(0)),
+        }
+    }
+}
+
+impl LoopCommand {
+    fn run(&mut self, shell: &mut Executor) -> ExitStatus {
+        let mut status = exit_status(0);
+        loop {
+            let guard_status = execute_condition(&mut self.guard, shell);
+            if shell.control_flow.is_some() {
+                if let Iteration::Stop = end_iteration(shell) {
+                    break;
+                }
+                continue;
+            }
+            if guard_status.success() == self.until {
+                break;
+            }
+            status = execute_list(&mut self.body, shell);
+            if let Iteration::Stop = end_iteration(shell) {
+                break;
+            }
+        }
+        status
+    }
+}
+
+impl Execute for LoopCommand {
+    fn execute(&mut self, shell: &mut Executor) -> Result<ExitStatus> {
+        shell.loop_depth += 1;
+        let status = self.run(shell);
+        shell.loop_depth -= 1;
+        Ok(status)
+    }
+}
+
+impl ForCommand {
+    fn run(&mut self, shell: &mut Executor, values: Vec<OsString>) -> ExitStatus {
+        let mut status = exit_status(0);
+        for value in values {
+            shell.variables.set(&self.var, value);
+            status = execute_list(&mut self.body, shell);
+            if let Iteration::Stop = end_iteration(shell) {
+                break;
+            }
+        }
+        status
+    }
+}
+
+impl Execute for ForCommand {
+    fn execute(&mut self, shell: &mut Executor) -> Result<ExitStatus> {
+        let values = match &self.words {
+            Some(words) => expand::expand_words(shell, words)?,
+            None        => shell.positional.clone(),
+        };
+        shell.loop_depth += 1;
+        let status = self.run(shell, values);
+        shell.loop_depth -= 1;
+        Ok(status)
+    }
+}
+
+/// `case word in pattern) ... ;; esac`
+pub struct CaseCommand {
+    pub(super) word: TopLevelWordAlias,
+    pub(super) arms: Vec<(Vec<TopLevelWordAlias>, Vec<BoxedExecutable>)>,
+}
+
+impl Execute for CaseCommand {
+    fn execute(&mut self, shell: &mut Executor) -> Result<ExitStatus> {
+        let word = expand::expand_word(shell, &self.word)?;
+        for (patterns, body) in &mut self.arms {
+            for pattern in patterns.iter() {
+                let pattern = Pattern::new(&expand::expand_pattern(shell, pattern)?);
+                if pattern.matches(&word.to_string_lossy()) {
+                    return Ok(execute_list(body, shell));
+                }
+            }
+        }
+        Ok(exit_status(0))
+    }
+}
+
+impl Execute for BraceGroup {
+    fn execute(&mut self, shell: &mut Executor) -> Result<ExitStatus> {
+        Ok(execute_list(&mut self.commands, shell))
+    }
+}
+
+impl Execute for RedirectedCompound {
+    //the redirects are applied to the shell's own fds while the command
+    //runs, and put back afterwards
+    fn execute(&mut self, shell: &mut Executor) -> Result<ExitStatus> {
+        let redirects = convert_redirects(shell, &self.redirects)?;
+        execute_redirected(self.command.as_mut(), &redirects, shell)
+    }
+}
+
+impl Execute for CompoundStage {
+    //only a pipeline of one stage, as in `! while ...`, runs it directly
+    fn execute(&mut self, shell: &mut Executor) -> Result<ExitStatus> {
+        self.command.execute(shell)
+    }
+}
+
+impl Pipe for CompoundStage {
+    fn get_child(&mut self, shell: &mut Executor) -> Result<Pid> {
+        let (stdin, stdout) = (self.stdin.take(), self.stdout.take());
+        let command = &mut self.command;
+        fork_subshell(shell, stdin, stdout, |shell| execute_or_report(command.as_mut(), shell))
+    }
+
+    fn pipe_in(&mut self, in_pipe: PipeReader) {
+        self.stdin = Some(in_pipe.into());
+    }
+
+    fn pipe_out(&mut self, out_pipe: PipeWriter) {
+        self.stdout = Some(out_pipe.into());
+    }
+}
@@ -0,0 +1,274 @@
+use crate::command::*;
+use conch_parser::ast;
+use super::rewrite::{HERE_STRING, PROCESS_IN, PROCESS_OUT};
+
+/// Renders a parsed command back into shell source, for places that show a
+/// command to the user such as the job table
+pub fn command_text(command: &ast::TopLevelCommand<String>) -> String {
+    match &command.0 {
+        ast::Command::Job(and_or)  => format!("{} &", and_or_text(and_or)),
+        ast::Command::List(and_or) => and_or_text(and_or),
+    }
+}
+
+pub fn and_or_text(and_or_list: &ast::AndOrList<ListableCommandAlias>) -> String {
+    let mut text = listable_text(&and_or_list.first);
+    for and_or in &and_or_list.rest {
+        match and_or {
+            ast::AndOr::And(command) => text = format!("{} && {}", text, listable_text(command)),
+            ast::AndOr::Or(command)  => text = format!("{} || {}", text, listable_text(command)),
+        }
+    }
+    text
+}
+
+fn listable_text(listable: &ListableCommandAlias) -> String {
+    match listable {
+        ast::ListableCommand::Single(command) => pipeable_text(command),
+        ast::ListableCommand::Pipe(bang, commands) => pipeline_text(*bang, commands),
+    }
+}
+
+pub fn pipeline_text(bang: bool, commands: &[PipeableCommandAlias]) -> String {
+    let pipeline: Vec<String> = commands.iter().map(pipeable_text).collect();
+    let bang = if bang { "! " } else { "" };
+    format!("{}{}", bang, pipeline.join(" | "))
+}
+
+fn pipeable_text(pipeable: &PipeableCommandAlias) -> String {
+    match pipeable {
+        ast::PipeableCommand::Simple(simple) => simple_text(simple),
+        ast::PipeableCommand::Compound(compound) => compound_text(compound),
+        ast::PipeableCommand::FunctionDef(name, body) => format!("{}() {}", name, compound_text(body)),
+    }
+}
+
+fn simple_text(simple: &SimpleCommandAlias) -> String {
+    let mut parts = vec![];
+    for item in &simple.redirects_or_env_vars {
+        match item {
+            ast::RedirectOrEnvVar::Redirect(redirect) => parts.push(redirect_text(redirect)),
+            ast::RedirectOrEnvVar::EnvVar(name, value) => {
+                let value = value.as_ref().map(word_text).unwrap_or_default();
+                parts.push(format!("{}={}", name, value));
+            },
+        }
+    }
+    for item in &simple.redirects_or_cmd_words {
+        match item {
+            ast::RedirectOrCmdWord::Redirect(redirect) => parts.push(redirect_text(redirect)),
+            ast::RedirectOrCmdWord::CmdWord(word) => parts.push(word_text(word)),
+        }
+    }
+    parts.join(" ")
+}
+
+pub fn compound_text(compound: &CompoundCommandAlias) -> String {
+    let mut text = match &compound.kind {
+        ast::CompoundCommandKind::Brace(commands) => format!("{{ {}; }}", list_text(commands)),
+        ast::CompoundCommandKind::Subshell(commands) => format!("({})", list_text(commands)),
+        ast::CompoundCommandKind::While(guard_body) => {
+            format!("while {}; do {}; done", list_text(&guard_body.guard), list_text(&guard_body.body))
+        },
+        ast::CompoundCommandKind::Until(guard_body) => {
+            format!("until {}; do {}; done", list_text(&guard_body.guard), list_text(&guard_body.body))
+        },
+        ast::CompoundCommandKind::If { conditionals, else_branch } => {
+            let mut text = String::new();
+            for (i, conditional) in conditionals.iter().enumerate() {
+                let keyword = if i == 0 { "if" } else { " elif" };
+                text.push_str(&format!("{} {}; then {};", keyword, list_text(&conditional.guard), list_text(&conditional.body)));
+            }
+            if let Some(commands) = else_branch {
+                text.push_str(&format!(" else {};", list_text(commands)));
+            }
+            text + " fi"
+        },
+        ast::CompoundCommandKind::For { var, words, body } => {
+            let words = match words {
+                Some(words) => {
+                    let words: Vec<String> = words.iter().map(word_text).collect();
+                    format!(" in {}", words.join(" "))
+                },
+                None => String::new(),
+            };
+            format!("for {}{}; do {}; done", var, words, list_text(body))
+        },
+        ast::CompoundCommandKind::Case { word, arms } => {
+            let mut text = format!("case {} in", word_text(word));
+            for arm in arms {
+                let patterns: Vec<String> = arm.patterns.iter().map(word_text).collect();
+                text.push_str(&format!(" {}) {};;", patterns.join("|"), list_text(&arm.body)));
+            }
+            text + " esac"
+        },
+    };
+    for redirect in &compound.io {
+        text.push(' ');
+        text.push_str(&redirect_text(redirect));
+    }
+    text
+}
+
+fn list_text(commands: &[ast::TopLevelCommand<String>]) -> String {
+    let mut text = String::new();
+    for command in commands {
+        if !text.is_empty() {
+            //a trailing `&` already separates the commands
+            text.push_str(if text.ends_with('&') { " " } else { "; " });
+        }
+        text.push_str(&command_text(command));
+    }
+    text
+}
+
+fn redirect_text(redirect: &RedirectAlias) -> String {
+    let (fd, operator, word) = match redirect {
+        ast::Redirect::Read(fd, word)      => (fd, "<", word),
+        ast::Redirect::Write(fd, word)     => (fd, ">", word),
+        ast::Redirect::ReadWrite(fd, word) => (fd, "<>", word),
+        ast::Redirect::Append(fd, word)    => (fd, ">>", word),
+        ast::Redirect::Clobber(fd, word)   => (fd, ">|", word),
+        ast::Redirect::Heredoc(fd, word)   => (fd, "<<", word),
+        ast::Redirect::DupRead(fd, word)   => (fd, "<&", word),
+        ast::Redirect::DupWrite(fd, word)  => (fd, ">&", word),
+    };
+    let fd = fd.map(|fd| fd.to_string()).unwrap_or_default();
+    let word = word_text(word);
+    match word.strip_prefix(HERE_STRING) {
+        Some(word) => format!("{}<<<{}", fd, word),
+        None       => format!("{}{}{}", fd, operator, word),
+    }
+}
+
+pub fn word_text(word: &TopLevelWordAlias) -> String {
+    let text: String = match &word.0 {
+        ast::ComplexWord::Single(word) => word_part_text(word),
+        ast::ComplexWord::Concat(words) => words.iter().map(word_part_text).collect(),
+    };
+    //process substitutions are shown as they were written
+    text.replace(&format!("{}$(", PROCESS_IN), "<(")
+        .replace(&format!("{}$(", PROCESS_OUT), ">(")
+}
+
+fn word_part_text(word: &WordAlias) -> String {
+    match word {
+        ast::Word::Simple(simple) => simple_word_text(simple),
+        ast::Word::DoubleQuoted(simples) => {
+            let text: String = simples.iter().map(simple_word_text).collect();
+            format!("\"{}\"", text)
+        },
+        ast::Word::SingleQuoted(text) => format!("'{}'", text),
+    }
+}
+
+fn simple_word_text(simple: &SimpleWordAlias) -> String {
+    match simple {
+        ast::SimpleWord::Literal(text) => text.clone(),
+        ast::SimpleWord::Escaped(text) => format!("\\{}", text),
+        ast::SimpleWord::Param(param) => format!("${}", parameter_text(param)),
+        ast::SimpleWord::Subst(subst) => substitution_text(subst),
+        ast::SimpleWord::Star        => String::from("*"),
+        ast::SimpleWord::Question    => String::from("?"),
+        ast::SimpleWord::SquareOpen  => String::from("["),
+        ast::SimpleWord::SquareClose => String::from("]"),
+        ast::SimpleWord::Tilde       => String::from("~"),
+        ast::SimpleWord::Colon       => String::from(":"),
+    }
+}
+
+/// The name of a parameter without its leading `$`
+fn parameter_text(param: &ast::Parameter<String>) -> String {
+    match param {
+        ast::Parameter::At       => String::from("@"),
+        ast::Parameter::Star     => String::from("*"),
+        ast::Parameter::Pound    => String::from("#"),
+        ast::Parameter::Question => String::from("?"),
+        ast::Parameter::Dash     => String::from("-"),
+        ast::Parameter::Dollar   => String::from("$"),
+        ast::Parameter::Bang     => String::from("!"),
+        ast::Parameter::Var(name)       => name.clone(),
+        ast::Parameter::Positional(n)   => n.to_string(),
+    }
+}
+
+fn substitution_text(subst: &ParameterAlias) -> String {
+    let with_word = |param: &ast::Parameter<String>, operator: &str, word: &Option<TopLevelWordAlias>| {
+        let word = word.as_ref().map(word_text).unwrap_or_default();
+        format!("${{{}{}{}}}", parameter_text(param), operator, word)
+    };
+    let colon = |colon: &bool| if *colon { ":" } else { "" };
+    match subst {
+        ast::ParameterSubstitution::Command(commands) => format!("$({})", list_text(commands)),
+        ast::ParameterSubstitution::Len(param) => format!("${{#{}}}", parameter_text(param)),
+        ast::ParameterSubstitution::Arith(arith) => {
+            let text = arith.as_ref().map(arithmetic_text).unwrap_or_default();
+            format!("$(({}))", text)
+        },
+        ast::ParameterSubstitution::Default(c, param, word)     => with_word(param, &format!("{}-", colon(c)), word),
+        ast::ParameterSubstitution::Assign(c, param, word)      => with_word(param, &format!("{}=", colon(c)), word),
+        ast::ParameterSubstitution::Error(c, param, word)       => with_word(param, &format!("{}?", colon(c)), word),
+        ast::ParameterSubstitution::Alternative(c, param, word) => with_word(param, &format!("{}+", colon(c)), word),
+        ast::ParameterSubstitution::RemoveSmallestSuffix(param, word) => with_word(param, "%", word),
+        ast::ParameterSubstitution::RemoveLargestSuffix(param, word)  => with_word(param, "%%", word),
+        ast::ParameterSubstitution::RemoveSmallestPrefix(param, word) => with_word(param, "#", word),
+        ast::ParameterSubstitution::RemoveLargestPrefix(param, word)  => with_word(param, "##", word),
+    }
+}
+
+fn arithmetic_text(arith: &ast::Arithmetic<String>) -> String {
+    use ast::Arithmetic::*;
+    let binary = |left: &ast::Arithmetic<String>, operator: &str, right: &ast::Arithmetic<String>| {
+        format!("{} {} {}", operand_text(left), operator, operand_text(right))
+    };
+    match arith {
+        Var(name)       => name.clone(),
+        Literal(value)  => value.to_string(),
+        PostIncr(name)  => format!("{}++", name),
+        PostDecr(name)  => format!("{}--", name),
+        PreIncr(name)   => format!("++{}", name),
+        PreDecr(name)   => format!("--{}", name),
+        UnaryPlus(expr)  => format!("+{}", operand_text(expr)),
+        UnaryMinus(expr) => format!("-{}", operand_text(expr)),
+        LogicalNot(expr) => format!("!{}", operand_text(expr)),
+        BitwiseNot(expr) => format!("~{}", operand_text(expr)),
+        Pow(left, right)        => binary(left, "**", right),
+        Mult(left, right)       => binary(left, "*", right),
+        Div(left, right)        => binary(left, "/", right),
+        Modulo(left, right)     => binary(left, "%", right),
+        Add(left, right)        => binary(left, "+", right),
+        Sub(left, right)        => binary(left, "-", right),
+        ShiftLeft(left, right)  => binary(left, "<<", right),
+        ShiftRight(left, right) => binary(left, ">>", right),
+        Less(left, right)       => binary(left, "<", right),
+        LessEq(left, right)     => binary(left, "<=", right),
+        Great(left, right)      => binary(left, ">", right),
+        GreatEq(left, right)    => binary(left, ">=", right),
+        Eq(left, right)         => binary(left, "==", right),
+        NotEq(left, right)      => binary(left, "!=", right),
+        BitwiseAnd(left, right) => binary(left, "&", right),
+        BitwiseXor(left, right) => binary(left, "^", right),
+        BitwiseOr(left, right)  => binary(left, "|", right),
+        LogicalAnd(left, right) => binary(left, "&&", right),
+        LogicalOr(left, right)  => binary(left, "||", right),
+        Ternary(cond, then, otherwise) => {
+            format!("{} ? {} : {}", operand_text(cond), operand_text(then), operand_text(otherwise))
+        },
+        Assign(name, expr) => format!("{} = {}", name, arithmetic_text(expr)),
+        Sequence(exprs) => {
+            let exprs: Vec<String> = exprs.iter().map(arithmetic_text).collect();
+            exprs.join(", ")
+        },
+    }
+}
+
+/// An operand of an operator, in parentheses unless it is a single term,
+/// so the text groups the same way as the parsed expression
+fn operand_text(arith: &ast::Arithmetic<String>) -> String {
+    use ast::Arithmetic::*;
+    match arith {
+        Var(_) | Literal(_) | PostIncr(_) | PostDecr(_) | PreIncr(_) | PreDecr(_)
+            | UnaryPlus(_) | UnaryMinus(_) | LogicalNot(_) | BitwiseNot(_) => arithmetic_text(arith),
+        _ => format!("({})", arithmetic_text(arith)),
+    }
+}
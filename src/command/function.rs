@@ -0,0 +1,82 @@
+use crate::execute::{Execute, Pipe, Executor, ControlFlow, exit_status, fork_subshell};
+use crate::command::CompoundCommandAlias;
+use crate::command::parser::parse_compound;
+use nix::unistd::Pid;
+use std::io::{PipeReader, PipeWriter};
+use std::os::fd::OwnedFd;
+use std::os::unix::process::ExitStatusExt;
+use std::process::ExitStatus;
+use std::ffi::OsString;
+use std::rc::Rc;
+use std::mem;
+use anyhow::{anyhow, Result};
+
+/// How deeply function calls may nest before a call is refused, well short
+/// of what would overflow the shell's stack
+const MAX_FUNCTION_DEPTH: u32 = 1000;
+
+/// `name() body`, which adds the function to the shell's table when run
+pub struct FunctionDefinition {
+    pub(super) name: String,
+    pub(super) body: Rc<CompoundCommandAlias>,
+    pub(super) stdin: Option<OwnedFd>,
+    pub(super) stdout: Option<OwnedFd>,
+}
+
+/// A call to a defined function, with the words it was called with
+pub struct FunctionCommand {
+    pub(super) body: Rc<CompoundCommandAlias>,
+    pub(super) args: Vec<OsString>,
+}
+
+impl Execute for FunctionDefinition {
+    fn execute(&mut self, shell: &mut Executor) -> Result<ExitStatus> {
+        shell.functions.insert(self.name.clone(), self.body.clone());
+        Ok(ExitStatus::from_raw(0))
+    }
+}
+
+impl Pipe for FunctionDefinition {
+    //as a pipeline stage the definition only lasts as long as the child
+    fn get_child(&mut self, shell: &mut Executor) -> Result<Pid> {
+        let (stdin, stdout) = (self.stdin.take(), self.stdout.take());
+        fork_subshell(shell, stdin, stdout, |_| ExitStatus::from_raw(0))
+    }
+
+    fn pipe_in(&mut self, in_pipe: PipeReader) {
+        self.stdin = Some(in_pipe.into());
+    }
+
+    fn pipe_out(&mut self, out_pipe: PipeWriter) {
+        self.stdout = Some(out_pipe.into());
+    }
+}
+
+impl Execute for FunctionCommand {
+    fn execute(&mut self, shell: &mut Executor) -> Result<ExitStatus> {
+        if shell.function_depth >= MAX_FUNCTION_DEPTH {
+            return Err(anyhow!("{}: maximum function nesting level exceeded ({})", self.args[0].to_string_lossy(), MAX_FUNCTION_DEPTH));
+        }
+        //the body's redirects, as in `name() { ...; } > log`, apply to each call
+        let mut body = parse_compound(&self.body)?;
+        let positional = mem::replace(&mut shell.positional, self.args[1..].to_vec());
+        //loops in the caller can't be broken out of from inside the function
+        let loop_depth = mem::replace(&mut shell.loop_depth, 0);
+        shell.function_depth += 1;
+        shell.variables.push_scope();
+
+        let result = body.execute(shell);
+
+        shell.variables.pop_scope();
+        shell.function_depth -= 1;
+        shell.loop_depth = loop_depth;
+        shell.positional = positional;
+        match shell.control_flow.take() {
+            Some(ControlFlow::Return(code)) => Ok(exit_status(code)),
+            control_flow => {
+                shell.control_flow = control_flow;
+                result
+            },
+        }
+    }
+}
@@ -1,19 +1,35 @@
 mod parser;
-
-use nix::unistd::ForkResult;
-use nix::sys::wait::WaitStatus;
+mod compound;
+mod function;
+mod display;
+mod redirect;
+mod rewrite;
+mod scan;
+
+use nix::unistd::Pid;
 use conch_parser::lexer::Lexer;
-use conch_parser::parse::DefaultParser;
+use conch_parser::parse::{DefaultParser, ParseError};
 use conch_parser::ast;
 use std::process;
-use std::collections::VecDeque;
 use std::process::{Stdio, ExitStatus};
-use std::io::{Write, Read};
+use std::io::{self, PipeReader, PipeWriter};
+use std::os::fd::OwnedFd;
 use std::os::unix::process::ExitStatusExt;
-use std::fs::{File, OpenOptions};
-use crate::execute::{Execute, Pipe};
+use std::fs::File;
+use std::ffi::OsString;
+use crate::execute::{Execute, Pipe, Executor, exit_status, execute_or_report, execute_list, fork_subshell, spawn, wait_for, wait_for_job};
+use crate::execute::jobs::Job;
+use crate::execute::traps;
+use crate::execute::variables::Variable;
+use crate::expand;
 use crate::builtins;
-use parser::*;
+pub use parser::generate_command;
+pub use compound::*;
+pub use function::*;
+pub use display::*;
+pub use rewrite::{PROCESS_IN, PROCESS_OUT};
+pub use scan::scan;
+use redirect::{RedirectGuard, convert_redirects, execute_redirected, redirect_command};
 use anyhow::{anyhow, Result};
 
 pub type TopLevelCommandList = Vec<ast::TopLevelCommand<String>>;
@@ -26,31 +42,60 @@ pub enum Conjunction {
 
 #[derive(Debug)]
 pub enum Redirect {
-    Read(Option<u16>, String),
-    Write(Option<u16>, String),
-    Append(Option<u16>, String),
-    ReadWrite(Option<u16>, String),
+    Read(Option<u16>, OsString),
+    Write(Option<u16>, OsString),
+    /// `>` while noclobber is set, which won't overwrite a regular file
+    WriteNew(Option<u16>, OsString),
+    Append(Option<u16>, OsString),
+    ReadWrite(Option<u16>, OsString),
     DupRead(Option<u16>, String),
     DupWrite(Option<u16>, String),
-    Clobber(Option<u16>, String),
-    Heredoc(Option<u16>, String),
+    Clobber(Option<u16>, OsString),
+    /// A here-document or here-string, holding its expanded text
+    Heredoc(Option<u16>, OsString),
 }
 
 pub struct SubshellCommand {
     commands: Vec<Box<dyn Execute>>,
+    text: String,
+}
+
+/// A command list run with `&`, which the shell doesn't wait for
+pub struct BackgroundCommand {
+    command: Box<dyn Execute>,
+    text: String,
 }
 
 pub struct SingleCommand {
     command: Box<dyn Execute>,
 }
 
+/// A simple command as written, with its words, assignments and redirect
+/// targets still unexpanded. What kind of command it turns out to be is
+/// only known once it has been expanded at execution time.
 pub struct SimpleCommand {
-    command: process::Command,
+    env_vars: Vec<(String, Option<TopLevelWordAlias>)>,
+    words: Vec<TopLevelWordAlias>,
+    redirects: Vec<RedirectAlias>,
+    stdin: Option<OwnedFd>,
+    stdout: Option<OwnedFd>,
+}
+
+/// The assignments written before a command, expanded
+type EnvVars = Vec<(String, OsString)>;
+
+enum PreparedCommand {
+    Assignments,
+    Function(FunctionCommand, Vec<Redirect>, EnvVars),
+    Builtin(BuiltinCommand, Vec<Redirect>, EnvVars),
+    External(process::Command),
+    Redirect(RedirectCommand),
 }
 
 pub struct PipeCommands {
     pub commands: Vec<Box<dyn Pipe>>,
     bang: bool,
+    text: String,
 }
 
 pub struct AndOrCommandList {
@@ -66,341 +111,335 @@ pub struct AndOrCommand {
 #[derive(Debug)]
 pub struct RedirectCommand {
     command: process::Command,
-    redirect: Redirect,
+    /// Applied in the order they were written
+    redirects: Vec<Redirect>,
 }
 
 #[derive(Debug)]
 pub struct BuiltinCommand {
-    args: Vec<String>,
+    args: Vec<OsString>,
 }
 
 pub fn parse_into_commands(input: &str) -> Result<Vec<Box<dyn Execute>>> {
     let mut boxed_command_list: Vec<Box<dyn Execute>> = vec![];
+    let input = rewrite::rewrite(input);
     let lex = Lexer::new(input.chars());
     let parser = DefaultParser::new(lex);
 
     let ast_com_list: TopLevelCommandList = parser.into_iter()
                                                 .filter_map(|r| r.map_err(|e| eprintln!("Arsh command parse error: {e}")).ok())
                                                 .collect();
-    for ast_command in ast_com_list {
+    for ast_command in &ast_com_list {
         let boxed_command = generate_command(ast_command)?;
         boxed_command_list.push(boxed_command);
     }
     Ok(boxed_command_list)
 }
 
+/// Whether the input ends partway through a command, so that more lines
+/// could finish it
+pub fn is_incomplete(input: &str) -> bool {
+    let input = rewrite::rewrite(input);
+    let parser = DefaultParser::new(Lexer::new(input.chars()));
+    parser.into_iter().any(|result| match result {
+        Err(ParseError::UnexpectedEOF | ParseError::Unmatched(..)) => true,
+        Err(ParseError::IncompleteCmd(_, _, _, pos)) => pos.byte >= input.len(),
+        _ => false,
+    })
+}
+
 impl Execute for SingleCommand {
-    fn execute(&mut self) -> Result<ExitStatus> {
-        self.command.execute()
+    fn execute(&mut self, shell: &mut Executor) -> Result<ExitStatus> {
+        self.command.execute(shell)
+    }
+}
+
+impl SimpleCommand {
+    /// Expands the command's words, assignments and redirect targets and
+    /// works out what should run
+    fn prepare(&mut self, shell: &mut Executor) -> Result<PreparedCommand> {
+        shell.substitution_status = None;
+        let args = expand::expand_words(shell, &self.words)?;
+        let redirects = convert_redirects(shell, &self.redirects)?;
+        let mut env_vars = vec![];
+        for (name, value) in &self.env_vars {
+            let value = match value {
+                Some(word) => expand::expand_assignment(shell, word)?,
+                None       => OsString::new(),
+            };
+            env_vars.push((name.clone(), value));
+        }
+        if args.is_empty() {
+            //with no command, redirects just create or open their files
+            for redirect in &redirects {
+                redirect.resolve()?;
+            }
+            for (name, value) in env_vars {
+                shell.variables.set(&name, value);
+            }
+            return Ok(PreparedCommand::Assignments);
+        }
+        if let Some(body) = args[0].to_str().and_then(|name| shell.functions.get(name)) {
+            return Ok(PreparedCommand::Function(FunctionCommand{ body: body.clone(), args }, redirects, env_vars));
+        }
+        if let Some(builtin) = build_builtin_command(&args) {
+            return Ok(PreparedCommand::Builtin(builtin, redirects, env_vars));
+        }
+        //arguments go to the command as the exact bytes they were expanded to
+        let mut command = process::Command::new(&args[0]);
+        command.args(&args[1..]);
+        //assignments before a command only apply to that command's environment
+        command.envs(env_vars.iter().map(|(name, value)| (name, value)));
+        if let Some(stdin) = self.stdin.take() {
+            command.stdin(Stdio::from(stdin));
+        }
+        if let Some(stdout) = self.stdout.take() {
+            command.stdout(Stdio::from(stdout));
+        }
+        if redirects.is_empty() {
+            Ok(PreparedCommand::External(command))
+        }
+        else {
+            Ok(PreparedCommand::Redirect(RedirectCommand{ command, redirects }))
+        }
     }
+}
 
-    fn execute_to_string(&mut self) -> Result<String> {
-        self.command.execute_to_string()
+impl SimpleCommand {
+    fn execute_prepared(&mut self, shell: &mut Executor) -> Result<ExitStatus> {
+        match self.prepare(shell)? {
+            //`x=$(cmd)` has the status of the substitution
+            PreparedCommand::Assignments => Ok(shell.substitution_status.take().unwrap_or(exit_status(0))),
+            PreparedCommand::Function(mut function, redirects, env_vars) => execute_assigned(&mut function, &redirects, env_vars, shell),
+            PreparedCommand::Builtin(builtin, redirects, _) if builtin.args == ["exec"] => {
+                //`exec` with only redirects applies them to the shell itself
+                RedirectGuard::apply(&redirects)?.persist();
+                Ok(exit_status(0))
+            },
+            PreparedCommand::Builtin(mut builtin, redirects, env_vars) => execute_assigned(&mut builtin, &redirects, env_vars, shell),
+            PreparedCommand::Redirect(mut redirect) => redirect.execute(shell),
+            PreparedCommand::External(mut command) => {
+                shell.job_pgid = None;
+                let child = spawn(shell, &mut command)?;
+                wait_for_job(shell, vec![child], command_line(&command))
+            },
+        }
     }
 }
 
 impl Execute for SimpleCommand {
-    fn execute(&mut self) -> Result<ExitStatus> {
-        let mut child = self.command.spawn()?;
-        let status = child.wait()?;
-        Ok(status)
+    fn execute(&mut self, shell: &mut Executor) -> Result<ExitStatus> {
+        traps::run_debug(shell);
+        self.execute_prepared(shell)
     }
+}
 
-    fn execute_to_string(&mut self) -> Result<String> {
-        self.command.stdout(Stdio::piped());
-        let mut child = self.command.spawn()?;
-        child.wait()?;
-        let output_opt = child.stdout;
-        let mut buf = String::new();
-        if let Some(mut output) = output_opt {
-            let _bytes_read = output.read_to_string(&mut buf)?;
-        }
-        Ok(buf)
+/// Runs a builtin or function with the assignments written before it set
+/// and exported only while it runs, as `IFS=: read a b` expects and so
+/// that the commands a function runs see them
+fn execute_assigned(command: &mut dyn Execute, redirects: &[Redirect], env_vars: EnvVars, shell: &mut Executor) -> Result<ExitStatus> {
+    let saved: Vec<(String, Option<Variable>)> = env_vars.iter()
+        .map(|(name, _)| (name.clone(), shell.variables.save(name)))
+        .collect();
+    for (name, value) in env_vars {
+        shell.variables.set(&name, value);
+        shell.variables.export(&name);
+    }
+    let result = execute_redirected(command, redirects, shell);
+    for (name, var) in saved.into_iter().rev() {
+        shell.variables.restore(&name, var);
+    }
+    result
+}
 
+impl SimpleCommand {
+    /// Runs a function or builtin as a pipeline stage, in a subshell whose
+    /// stdin and stdout are the stage's pipes. Whatever it writes to its
+    /// stdout goes down the pipeline, and anything it changes in the
+    /// shell only lasts as long as the stage, as with a real subshell.
+    fn fork_stage(&mut self, shell: &mut Executor, command: &mut dyn Execute, redirects: &[Redirect], env_vars: EnvVars) -> Result<Pid> {
+        let (stdin, stdout) = (self.stdin.take(), self.stdout.take());
+        fork_subshell(shell, stdin, stdout, |shell| {
+            match execute_assigned(command, redirects, env_vars, shell) {
+                Ok(status) => status,
+                Err(e)     => {
+                    eprintln!("Execution error: {}", e);
+                    exit_status(1)
+                },
+            }
+        })
     }
 }
 
 impl Pipe for SimpleCommand {
-    fn get_child(&mut self) -> std::process::Child {
-        self.command.spawn().unwrap()
+    fn get_child(&mut self, shell: &mut Executor) -> Result<Pid> {
+        //the prepared command holds the shell's copies of any pipe ends and
+        //is dropped on return, so readers see EOF and writers a broken pipe
+        match self.prepare(shell)? {
+            PreparedCommand::External(mut command) => spawn(shell, &mut command),
+            PreparedCommand::Redirect(mut redirect) => redirect.get_child(shell),
+            PreparedCommand::Function(mut function, redirects, env_vars) => self.fork_stage(shell, &mut function, &redirects, env_vars),
+            PreparedCommand::Builtin(mut builtin, redirects, env_vars) => self.fork_stage(shell, &mut builtin, &redirects, env_vars),
+            PreparedCommand::Assignments => Err(anyhow!("Empty command in pipeline")),
+        }
     }
 
-    fn pipe_in(&mut self, in_pipe: process::ChildStdout) {
-        self.command.stdin(Stdio::from(in_pipe));
+    fn pipe_in(&mut self, in_pipe: PipeReader) {
+        self.stdin = Some(in_pipe.into());
     }
 
-    fn pipe_out(&mut self) {
-        self.command.stdout(Stdio::piped());
+    fn pipe_out(&mut self, out_pipe: PipeWriter) {
+        self.stdout = Some(out_pipe.into());
     }
 }
 
 fn negate_status(status: ExitStatus) -> ExitStatus {
     if status.success() {
-        ExitStatus::from_raw(1)
+        exit_status(1)
     }
     else {
         ExitStatus::from_raw(0)
     }
 }
 
-impl Execute for PipeCommands {
-    fn execute(&mut self) -> Result<ExitStatus> {
-        let pipe_status: ExitStatus = if self.commands.len() == 1 {
-            self.commands.pop().unwrap().execute()?
+impl PipeCommands {
+    fn execute_pipeline(&mut self, shell: &mut Executor) -> Result<ExitStatus> {
+        if self.commands.len() == 1 {
+            return self.commands[0].execute(shell);
         }
-        else {
-            let mut commands: VecDeque<Box<dyn Pipe>> = self.commands.drain(..).collect();
-            //initialize vars before loop
-            let mut first = commands.pop_front().unwrap();
-            first.pipe_out();
-            let mut first_child = first.get_child();
-            let mut status = first_child.wait()?;
-            let mut output_opt = first_child.stdout;
-            while let Some(mut simple_command) = commands.pop_front() {
-                if let Some(output) = output_opt {
-                    simple_command.pipe_in(output);
-                }
-                simple_command.pipe_out();
-                let mut child = simple_command.get_child();
-                status = child.wait()?;
-                output_opt = child.stdout;
+        traps::run_debug(shell);
+        self.spawn_all(shell, false)
+            .and_then(|(children, _)| wait_for_job(shell, children, self.text.clone()))
+    }
+
+    /// Spawns every stage of the pipeline before waiting on any of them, so
+    /// that producers and consumers run concurrently. The last stage writes
+    /// straight to the shell's stdout unless `capture` is set, in which case
+    /// the read end of its output pipe is returned alongside the children.
+    fn spawn_all(&mut self, shell: &mut Executor, capture: bool) -> Result<(Vec<Pid>, Option<PipeReader>)> {
+        let mut children: Vec<Pid> = vec![];
+        let mut output_opt: Option<PipeReader> = None;
+        shell.job_pgid = None;
+        let last = self.commands.len() - 1;
+        for (i, command) in self.commands.iter_mut().enumerate() {
+            if let Some(output) = output_opt.take() {
+                command.pipe_in(output);
+            }
+            if i != last || capture {
+                let (reader, writer) = io::pipe()?;
+                command.pipe_out(writer);
+                output_opt = Some(reader);
             }
-            if let Some(output) = output_opt {
-                let print_out_result: std::result::Result<Vec<u8>, std::io::Error> = output.bytes().collect();
-                let print_out = print_out_result.unwrap();
-                match std::io::stdout()
-                    .lock()
-                    .write_all(&print_out) {
-                        Ok(_) => {},
-                        Err(e) => eprintln!("Failed to write final output of pipe: {}", e),
+            match command.get_child(shell) {
+                Ok(child) => children.push(child),
+                Err(e) => {
+                    //close the read end so earlier stages see a broken pipe
+                    drop(output_opt);
+                    for child in children {
+                        let _ = wait_for(child);
                     }
+                    shell.job_pgid = None;
+                    return Err(e);
+                },
             }
-            status
-        };
-        if self.bang {
-            Ok(negate_status(pipe_status))
-        }
-        else {
-            Ok(pipe_status)
         }
+        Ok((children, output_opt))
     }
+}
 
-    fn execute_to_string(&mut self) -> Result<String> {
-        if self.commands.len() == 1 {
-            self.commands.pop().unwrap().execute_to_string()
+impl Execute for PipeCommands {
+    fn execute(&mut self, shell: &mut Executor) -> Result<ExitStatus> {
+        //a negated pipeline failing doesn't trigger the ERR trap
+        if self.bang {
+            shell.condition_depth += 1;
+        }
+        let pipe_status = self.execute_pipeline(shell);
+        if self.bang {
+            shell.condition_depth -= 1;
+        }
+        let pipe_status = pipe_status?;
+        if self.bang {
+            shell.err_handled = true;
+            Ok(negate_status(pipe_status))
         }
         else {
-            let mut commands: VecDeque<Box<dyn Pipe>> = self.commands.drain(..).collect();
-            //initialize vars before loop
-            let mut first = commands.pop_front().unwrap();
-            first.pipe_out();
-            let mut first_child = first.get_child();
-            let mut _status = first_child.wait()?;
-            let mut output_opt = first_child.stdout;
-            while let Some(mut simple_command) = commands.pop_front() {
-                if let Some(output) = output_opt {
-                    simple_command.pipe_in(output);
-                }
-                simple_command.pipe_out();
-                let mut child = simple_command.get_child();
-                _status = child.wait()?;
-                output_opt = child.stdout;
-            }
-            let mut buf = String::new();
-            if let Some(mut output) = output_opt {
-                let _bytes_read = output.read_to_string(&mut buf);
-            }
-            Ok(buf)
+            Ok(pipe_status)
         }
     }
 }
 
 impl Execute for AndOrCommandList {
-    fn execute(&mut self) -> Result<ExitStatus> {
-        let mut status = match self.first.execute() {
-            Ok(status) => status,
-            Err(e)     => {
-                eprintln!{"Execution error: {e}"};
-                ExitStatus::from_raw(1)
+    fn execute(&mut self, shell: &mut Executor) -> Result<ExitStatus> {
+        //every command but the last is tested as a condition
+        let last = self.rest.len();
+        let mut status = execute_and_or(self.first.as_mut(), last == 0, shell);
+        for (i, command) in self.rest.iter_mut().enumerate() {
+            if shell.control_flow.is_some() {
+                break;
             }
-        };
-        for command in &mut self.rest {
-            status = match command.conjunction {
+            //a skipped command leaves the status of the last one that ran
+            match command.conjunction {
                 Conjunction::And => {
                     if status.success() {
-                        command.execute()?
-                    }
-                    else {
-                        ExitStatus::from_raw(1)
+                        status = execute_and_or(command, i + 1 == last, shell);
                     }
                 },
                 Conjunction::Or => {
                     if !status.success() {
-                        command.execute()?
-                    }
-                    else {
-                        ExitStatus::from_raw(0)
+                        status = execute_and_or(command, i + 1 == last, shell);
                     }
                 },
-
             }
         }
         Ok(status)
     }
-
-    fn execute_to_string(&mut self) -> Result<String> {
-        let mut output = match self.first.execute_to_string() {
-            Ok(output) => { output },
-            Err(e)     => {
-                eprintln!{"Execution Error: {e}"};
-                String::new()
-            }
-        };
-        let mut res_string;
-        for command in &mut self.rest {
-            output += match command.conjunction {
-                Conjunction::And => {
-                    if !output.is_empty() {
-                        res_string = command.execute_to_string()?;
-                        res_string.trim()
-                    }
-                    else {
-                        ""
-                    }
-                },
-                Conjunction::Or => {
-                    if output.is_empty() {
-                        res_string = command.execute_to_string()?;
-                        res_string.trim()
-                    }
-                    else {
-                        ""
-                    }
-                },
-
-            }
-        }
-        Ok(output)
-    }
 }
 
 impl Execute for AndOrCommand {
-    fn execute(&mut self) -> Result<ExitStatus> {
-        self.command.execute()
-    }
-
-    fn execute_to_string(&mut self) -> Result<String> {
-        self.command.execute_to_string()
+    fn execute(&mut self, shell: &mut Executor) -> Result<ExitStatus> {
+        self.command.execute(shell)
     }
 }
 
 impl Execute for RedirectCommand {
-    fn execute(&mut self) -> Result<ExitStatus> {
-        self.add_redirect_to_command()?;
-        let mut child = self.command.spawn()?;
-        let status = child.wait()?;
-        Ok(status)
-
-    }
-
-    fn execute_to_string(&mut self) -> Result<String> {
-        self.command.stdout(Stdio::piped());
-        self.add_redirect_to_command()?;
-        let mut child = self.command.spawn()?;
-        child.wait()?;
-        let output_opt = child.stdout;
-        let mut buf = String::new();
-        if let Some(mut output) = output_opt {
-            let _bytes_read = output.read_to_string(&mut buf)?;
-        }
-        Ok(buf)
+    fn execute(&mut self, shell: &mut Executor) -> Result<ExitStatus> {
+        shell.job_pgid = None;
+        let child = self.spawn_redirected(shell)?;
+        wait_for_job(shell, vec![child], command_line(&self.command))
     }
 }
 
 impl Pipe for RedirectCommand {
-    fn get_child(&mut self) -> std::process::Child {
-        match self.add_redirect_to_command() {
-            Ok(_)  => {},
-            Err(e) => eprintln!("Failed to add redirect in pipe: {}", e),
-        }
-        self.command.spawn().unwrap()
+    fn get_child(&mut self, shell: &mut Executor) -> Result<Pid> {
+        self.spawn_redirected(shell)
     }
 
-    fn pipe_in(&mut self, in_pipe: process::ChildStdout) {
+    fn pipe_in(&mut self, in_pipe: PipeReader) {
         self.command.stdin(Stdio::from(in_pipe));
     }
 
-    fn pipe_out(&mut self) {
-        self.command.stdout(Stdio::piped());
+    fn pipe_out(&mut self, out_pipe: PipeWriter) {
+        self.command.stdout(Stdio::from(out_pipe));
     }
 }
 
 impl RedirectCommand {
-    fn add_redirect_to_command(&mut self) -> Result<()> {
-        match &self.redirect {
-            Redirect::Read(fd, filename) => {
-                let file = File::open(filename)?;
-                match fd {
-                    None    => self.command.stdin(Stdio::from(file)),
-                    Some(0) => self.command.stdin(Stdio::from(file)),
-                    _       => { return Err(anyhow!("Implement Redirect::Read from arbitrary FDs")); }
-                };
-            }
-            Redirect::Write(fd, filename) => {
-                let file = OpenOptions::new()
-                    .create(true)
-                    .truncate(true)
-                    .write(true)
-                    .open(filename)?;
-                match fd {
-                    None    => self.command.stdout(Stdio::from(file)),
-                    Some(1) => self.command.stdout(Stdio::from(file)),
-                    Some(2) => self.command.stderr(Stdio::from(file)),
-                    _       => { return Err(anyhow!("Implement Redirect::Write from arbitrary FDs")); }
-                };
-            }
-            Redirect::Append(fd, filename) => {
-                let file = OpenOptions::new()
-                    .create(true)
-                    .append(true)
-                    .write(true)
-                    .open(filename)?;
-                match fd {
-                    None    => self.command.stdout(Stdio::from(file)),
-                    Some(1) => self.command.stdout(Stdio::from(file)),
-                    Some(2) => self.command.stderr(Stdio::from(file)),
-                    _       => { return Err(anyhow!("Implement Redirect::Append from arbitrary FDs")); }
-                };
-            }
-            Redirect::ReadWrite(fd, filename) => {
-                let file = OpenOptions::new()
-                    .create(true)
-                    .write(true)
-                    .read(true)
-                    .open(filename)?;
-                match fd {
-                    None    => self.command.stdin(Stdio::from(file)),
-                    Some(0) => self.command.stdin(Stdio::from(file)),
-                    Some(1) => self.command.stdout(Stdio::from(file)),
-                    Some(2) => self.command.stderr(Stdio::from(file)),
-                    _       => { return Err(anyhow!("Implement Redirect::ReadWrite from arbitrary FDs")); }
-                };
-            }
-            Redirect::Clobber(_, _com) => { return Err(anyhow!("Implement Redirect::Clobber")); }
-            Redirect::Heredoc(_, _com) => { return Err(anyhow!("Implement Redirect::Heredoc")); }
-            Redirect::DupRead(_, _com) => { return Err(anyhow!("Implement Redirect::DupRead")); }
-            Redirect::DupWrite(_n, _m) => { return Err(anyhow!("Implement Redirect::DupWrite")); }
-        };
-        Ok(())
+    /// Spawns the command with its redirects applied after any pipes. The
+    /// shell's copies of the files are closed once the child has its own.
+    fn spawn_redirected(&mut self, shell: &mut Executor) -> Result<Pid> {
+        let _files = redirect_command(&mut self.command, &self.redirects)?;
+        spawn(shell, &mut self.command)
     }
 }
 
 impl Execute for BuiltinCommand {
-    fn execute(&mut self) -> Result<ExitStatus> {
-        if let Some(builtin) = self.args.first() {
+    fn execute(&mut self, shell: &mut Executor) -> Result<ExitStatus> {
+        //most builtins take text, and the rest the exact bytes
+        let args: Vec<String> = self.args.iter().map(|arg| arg.to_string_lossy().into_owned()).collect();
+        if let Some(builtin) = args.first() {
             match builtin.as_str() {
                 "cd" => {
-                    builtins::cd(&self.args)?;
+                    builtins::cd(shell, &self.args)?;
                 },
                 "pwd" | "/bin/pwd" => {
                     builtins::pwd()?;
@@ -409,7 +448,61 @@ impl Execute for BuiltinCommand {
                     builtins::exec(&self.args)?;
                 }
                 "exit" => {
-                    builtins::exit(&self.args)?;
+                    builtins::exit(shell, &args)?;
+                }
+                "export" => {
+                    builtins::export(shell, &self.args)?;
+                }
+                "unset" => {
+                    builtins::unset(shell, &args)?;
+                }
+                "shift" => {
+                    builtins::shift(shell, &args)?;
+                }
+                "set" => {
+                    builtins::set(shell, &self.args)?;
+                }
+                "break" => {
+                    builtins::break_loop(shell, &args)?;
+                }
+                "continue" => {
+                    builtins::continue_loop(shell, &args)?;
+                }
+                "return" => {
+                    builtins::return_function(shell, &args)?;
+                }
+                "local" => {
+                    builtins::local(shell, &self.args)?;
+                }
+                "let" => {
+                    return builtins::let_arithmetic(shell, &args);
+                }
+                "read" => {
+                    return builtins::read(shell, &args);
+                }
+                "wait" => {
+                    return builtins::wait(shell, &args);
+                }
+                "jobs" => {
+                    builtins::jobs(shell)?;
+                }
+                "fg" => {
+                    return builtins::fg(shell, &args);
+                }
+                "bg" => {
+                    builtins::bg(shell, &args)?;
+                }
+                "kill" => {
+                    builtins::kill(shell, &args)?;
+                }
+                "disown" => {
+                    builtins::disown(shell, &args)?;
+                }
+                "trap" => {
+                    builtins::trap(shell, &args)?;
+                }
+                "shopt" => {
+                    builtins::shopt(shell, &args)?;
                 }
                 _ => {
                     return Err(anyhow!("Malformed builtin"));
@@ -421,39 +514,105 @@ impl Execute for BuiltinCommand {
         }
         Ok(ExitStatus::from_raw(0))
     }
+}
 
-    fn execute_to_string(&mut self) -> Result<String> {
-        Err(anyhow!("String versions of builtins not yet implemented"))
+impl Execute for BackgroundCommand {
+    fn execute(&mut self, shell: &mut Executor) -> Result<ExitStatus> {
+        //without job control a background command mustn't read the terminal
+        let stdin = match shell.interactive {
+            true  => None,
+            false => Some(OwnedFd::from(File::open("/dev/null")?)),
+        };
+        let command = &mut self.command;
+        shell.job_pgid = None;
+        let child = fork_subshell(shell, stdin, None, |shell| execute_or_report(command.as_mut(), shell))?;
+        shell.last_bg_pid = Some(child.as_raw() as u32);
+        let id = shell.jobs.add(Job::new(shell.job_pgid.take(), vec![child], self.text.clone()));
+        if shell.interactive {
+            eprintln!("[{}] {}", id, child);
+        }
+        Ok(ExitStatus::from_raw(0))
     }
 }
 
 impl Execute for SubshellCommand {
-    fn execute(&mut self) -> Result<ExitStatus> {
-        match unsafe{ nix::unistd::fork()? } {
-            ForkResult::Parent{child: _} => {
-                match nix::sys::wait::wait()? {
-                    WaitStatus::Exited(_pid, code) => Ok(ExitStatus::from_raw(code)),
-                    _ => {
-                        eprintln!("waitpid exit error");
-                        Ok(ExitStatus::from_raw(1))
-                    }
-                }
-            },
-            ForkResult::Child => {
-                let mut status = ExitStatus::from_raw(0);
-                for command in &mut self.commands {
-                    status = command.execute()?;
-                }
-                let code = if let Some(code) = status.code() {
-                    code
+    fn execute(&mut self, shell: &mut Executor) -> Result<ExitStatus> {
+        let commands = &mut self.commands;
+        shell.job_pgid = None;
+        let child = fork_subshell(shell, None, None, |shell| execute_list(commands, shell))?;
+        wait_for_job(shell, vec![child], self.text.clone())
+    }
+}
+
+fn execute_and_or(command: &mut dyn Execute, last: bool, shell: &mut Executor) -> ExitStatus {
+    if last {
+        return execute_or_report(command, shell);
+    }
+    shell.condition_depth += 1;
+    let status = execute_or_report(command, shell);
+    shell.condition_depth -= 1;
+    status
+}
+
+/// The command line a spawned command was given, for display in the job table
+fn command_line(command: &process::Command) -> String {
+    let mut words = vec![command.get_program().to_string_lossy()];
+    words.extend(command.get_args().map(|arg| arg.to_string_lossy()));
+    words.join(" ")
+}
+
+fn build_builtin_command(in_args: &[OsString]) -> Option<BuiltinCommand> {
+    let mut args: Vec<OsString> = vec![];
+    if let Some(builtin) = in_args.first() {
+        match builtin.to_str()? {
+            "cd" | "pwd" | "/bin/pwd" | "exec" | "exit" | "export" | "unset" | "shift" | "set"
+            | "break" | "continue" | "return" | "local" | "let" | "read" | "wait"
+            | "jobs" | "fg" | "bg" | "kill" | "disown" | "trap" | "shopt" => {
+                for arg in in_args {
+                    args.push(arg.clone());
                 }
-                else { 0 };
-                std::process::exit(code)
+                Some(BuiltinCommand{ args })
             },
+            _ => None,
         }
     }
-
-    fn execute_to_string(&mut self) -> Result<String> {
-        Err(anyhow!("Cannot print subshell to string"))
+    else {
+        None
     }
 }
+
+// ********************************************
+// Decompose conch_parser's AST structure into useable type aliases
+// ********************************************
+pub type ListableCommandAlias = ast::ListableCommand<PipeableCommandAlias>;
+
+pub type PipeableCommandAlias =
+    ast::PipeableCommand
+        <String,
+        Box<SimpleCommandAlias>,
+        Box<CompoundCommandAlias>,
+        std::rc::Rc<CompoundCommandAlias>>;
+
+pub type SimpleCommandAlias =
+    ast::SimpleCommand<String, TopLevelWordAlias, RedirectAlias>;
+
+pub type CompoundCommandAlias =
+    ast::CompoundCommand<ast::CompoundCommandKind<String, TopLevelWordAlias, ast::TopLevelCommand<String>>, RedirectAlias>;
+
+pub type RedirectAlias =
+    ast::Redirect<TopLevelWordAlias>;
+
+pub type TopLevelWordAlias =
+    ast::TopLevelWord<String>;
+
+pub type ComplexWordAlias =
+    ast::ComplexWord<WordAlias>;
+
+pub type WordAlias =
+    ast::Word<String, SimpleWordAlias>;
+
+pub type SimpleWordAlias =
+    ast::SimpleWord<String, ast::Parameter<String>, Box<ParameterAlias>>;
+
+pub type ParameterAlias =
+    ast::ParameterSubstitution<ast::Parameter<String>, TopLevelWordAlias, ast::TopLevelCommand<String>, ast::Arithmetic<String>>;
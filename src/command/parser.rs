@@ -5,8 +5,8 @@ use anyhow::Result;
 
 type BoxedExecutable = Box<dyn Execute>;
 
-pub fn generate_command(top_level_command: ast::TopLevelCommand<String>) -> Result<BoxedExecutable> {
-    let boxed_command = parse_top_level(&top_level_command)?;
+pub fn generate_command(top_level_command: &ast::TopLevelCommand<String>) -> Result<BoxedExecutable> {
+    let boxed_command = parse_top_level(top_level_command)?;
     Ok(boxed_command)
 }
 
@@ -16,7 +16,10 @@ fn parse_top_level(top_level_command: &ast::TopLevelCommand<String>) -> Result<B
 
 fn parse_command(command: &ast::Command<ast::AndOrList<ListableCommandAlias>>) -> Result<BoxedExecutable> {
     match command {
-        ast::Command::Job(_)       => Err(anyhow!("Implement jobs")),
+        ast::Command::Job(and_or)  => {
+            let command = parse_and_or_list(and_or)?;
+            Ok(Box::new(BackgroundCommand { command, text: and_or_text(and_or) }))
+        },
         ast::Command::List(and_or) => parse_and_or_list(and_or),
     }
 }
@@ -24,268 +27,145 @@ fn parse_command(command: &ast::Command<ast::AndOrList<ListableCommandAlias>>) -
 fn parse_and_or_list(and_or_list: &ast::AndOrList<ListableCommandAlias>) -> Result<BoxedExecutable> {
     let first_unparsed = &and_or_list.first;
     let rest_unparsed = &and_or_list.rest;
-    let first = parse_listable(first_unparsed);
+    let first = parse_listable(first_unparsed)?;
     let mut rest: Vec<_> = vec![];
     for and_or_command in rest_unparsed {
-        rest.push(parse_and_or_command(and_or_command));
+        rest.push(parse_and_or_command(and_or_command)?);
     }
     let boxed_list = Box::new(AndOrCommandList{first, rest});
     Ok(boxed_list)
 }
 
-fn parse_listable(listable: &ListableCommandAlias) -> BoxedExecutable {
-    match listable {
-        ast::ListableCommand::Single(command) => Box::new(build_single_command(command)),
-        ast::ListableCommand::Pipe(bang, list) => Box::new(build_pipe_command(*bang, list)),
-    }
+fn parse_listable(listable: &ListableCommandAlias) -> Result<BoxedExecutable> {
+    let boxed_command: BoxedExecutable = match listable {
+        ast::ListableCommand::Single(command) => Box::new(build_single_command(command)?),
+        ast::ListableCommand::Pipe(bang, list) => Box::new(build_pipe_command(*bang, list)?),
+    };
+    Ok(boxed_command)
 }
 
-fn parse_and_or_command(and_or_command: &ast::AndOr<ListableCommandAlias>) -> AndOrCommand {
+fn parse_and_or_command(and_or_command: &ast::AndOr<ListableCommandAlias>) -> Result<AndOrCommand> {
     match and_or_command {
         ast::AndOr::And(command) => build_and_or_command(command, Conjunction::And),
         ast::AndOr::Or(command) => build_and_or_command(command, Conjunction::Or),
     }
 }
 
-fn parse_pipeable(pipeable: &PipeableCommandAlias) -> Box<dyn Pipe> {
-    match pipeable {
-        ast::PipeableCommand::Simple(simple) =>
-        {
-            let mut args: Vec<String> = vec![];
-            let mut redirs: Vec<&ast::Redirect<ast::TopLevelWord<String>>> = vec![];
-            let command_words = &simple.redirects_or_cmd_words;
-            for word in command_words {
-                match word {
-                    ast::RedirectOrCmdWord::Redirect(redir) => {
-                        redirs.push(redir);
-                    },
-                    ast::RedirectOrCmdWord::CmdWord(ast::TopLevelWord(complex_word)) => {
-                        let string_word = parse_complex_word(complex_word);
-                        args.push(string_word);
-                    }
-                }
-            }
-            let command: Box<dyn Pipe> = if redirs.len() == 1 {
-                    Box::new(build_redirect_command(&args, redirs.pop().unwrap()))
-                }
-                else {
-                    Box::new(build_simple(&args))
-                };
-            command
-        }
-        ast::PipeableCommand::Compound(_) => { todo!("Implement compound commands in pipes") },
-        ast::PipeableCommand::FunctionDef(_, _) => { todo!("Implement functions in pipes") },
-    }
-
+fn parse_pipeable(pipeable: &PipeableCommandAlias) -> Result<Box<dyn Pipe>> {
+    let boxed_command: Box<dyn Pipe> = match pipeable {
+        ast::PipeableCommand::Simple(simple) => Box::new(build_simple(simple)),
+        ast::PipeableCommand::Compound(compound) => Box::new(build_compound_stage(compound)?),
+        ast::PipeableCommand::FunctionDef(name, body) => Box::new(FunctionDefinition { name: name.clone(), body: body.clone(), stdin: None, stdout: None }),
+    };
+    Ok(boxed_command)
 }
 
-fn build_pipe_command(bang: bool, command_list: &Vec<PipeableCommandAlias>) -> PipeCommands {
+fn build_pipe_command(bang: bool, command_list: &Vec<PipeableCommandAlias>) -> Result<PipeCommands> {
     let mut commands = vec![];
 
     for command in command_list {
-        commands.push(parse_pipeable(command));
+        commands.push(parse_pipeable(command)?);
     }
-    PipeCommands{ commands, bang }
+    Ok(PipeCommands{ commands, bang, text: pipeline_text(bang, command_list) })
 }
 
-fn build_and_or_command(listable_command: &ListableCommandAlias, conjunction: Conjunction) -> AndOrCommand {
-    let command = parse_listable(listable_command);
-    AndOrCommand{command, conjunction}
+fn build_compound_stage(compound: &CompoundCommandAlias) -> Result<CompoundStage> {
+    let command = parse_compound(compound)?;
+    Ok(CompoundStage { command, stdin: None, stdout: None })
 }
 
-fn build_single_command(single: &PipeableCommandAlias) -> SingleCommand {
+fn build_and_or_command(listable_command: &ListableCommandAlias, conjunction: Conjunction) -> Result<AndOrCommand> {
+    let command = parse_listable(listable_command)?;
+    Ok(AndOrCommand{command, conjunction})
+}
+
+fn build_single_command(single: &PipeableCommandAlias) -> Result<SingleCommand> {
     let boxed_executable = match single {
         ast::PipeableCommand::Simple(simple) => parse_simple(simple),
-        ast::PipeableCommand::Compound(compound) => parse_compound(compound),
-        ast::PipeableCommand::FunctionDef(_, _) => { todo!("Implement functions in pipes") },
+        ast::PipeableCommand::Compound(compound) => parse_compound(compound)?,
+        ast::PipeableCommand::FunctionDef(name, body) => Box::new(FunctionDefinition { name: name.clone(), body: body.clone(), stdin: None, stdout: None }),
     };
-    SingleCommand { command: boxed_executable }
+    Ok(SingleCommand { command: boxed_executable })
 }
 
 fn parse_simple(simple: &SimpleCommandAlias) -> Box<dyn Execute> {
-    let mut args: Vec<String> = vec![];
-    let mut redirs: Vec<&ast::Redirect<ast::TopLevelWord<String>>> = vec![];
-    let command_words = &simple.redirects_or_cmd_words;
-    for word in command_words {
-        match word {
-            ast::RedirectOrCmdWord::Redirect(redir) => {
-                redirs.push(redir);
-            },
-            ast::RedirectOrCmdWord::CmdWord(ast::TopLevelWord(complex_word)) => {
-                let string_word = parse_complex_word(complex_word);
-                args.push(string_word);
-            }
-        }
-    }
-    let command: BoxedExecutable = if redirs.len() == 1 {
-            Box::new(build_redirect_command(&args, redirs.pop().unwrap()))
-        }
-        else if let Some(builtin) = build_builtin_command(&args) {
-            Box::new(builtin)
-        }
-        else {
-            Box::new(build_simple(&args))
-        };
-    command
+    Box::new(build_simple(simple))
 }
 
-fn parse_compound(compound: &CompoundCommandAlias) -> Box<dyn Execute> {
+pub(super) fn parse_compound(compound: &CompoundCommandAlias) -> Result<Box<dyn Execute>> {
     let kind = &compound.kind;
-    match kind {
+    let boxed_command: Box<dyn Execute> = match kind {
         ast::CompoundCommandKind::Subshell(command_list) => {
-            let commands_result: Result<Vec<_>> = command_list.iter().map(|com| parse_top_level(com)).collect();
-            let commands = match commands_result {
-                Ok(commands) => commands,
-                Err(e) => {
-                    eprintln!("Error parsing subshell: {e}");
-                    return Box::new(BuiltinCommand { args: vec![] });
-                },
+            let commands = parse_command_list(command_list)?;
+            Box::new(SubshellCommand { commands, text: compound_text(compound) })
+        },
+        ast::CompoundCommandKind::If { conditionals, else_branch } => {
+            let mut branches = vec![];
+            for conditional in conditionals {
+                let guard = parse_command_list(&conditional.guard)?;
+                let body = parse_command_list(&conditional.body)?;
+                branches.push((guard, body));
+            }
+            let else_branch = match else_branch {
+                Some(command_list) => Some(parse_command_list(command_list)?),
+                None               => None,
             };
-            Box::new(SubshellCommand { commands })
-        }
-            _ => todo!("Implement compound commands"),
-    }
-}
-
-fn build_simple(args: &[String]) -> SimpleCommand {
-    let mut command = process::Command::new(args[0].as_str());
-    for arg in &args[1..] {
-        command.arg(arg);
+            Box::new(IfCommand { branches, else_branch })
+        },
+        ast::CompoundCommandKind::While(guard_body) => {
+            Box::new(build_loop(guard_body, false)?)
+        },
+        ast::CompoundCommandKind::Until(guard_body) => {
+            Box::new(build_loop(guard_body, true)?)
+        },
+        ast::CompoundCommandKind::For { var, words, body } => {
+            let body = parse_command_list(body)?;
+            Box::new(ForCommand { var: var.clone(), words: words.clone(), body })
+        },
+        ast::CompoundCommandKind::Case { word, arms } => {
+            let mut case_arms = vec![];
+            for arm in arms {
+                let body = parse_command_list(&arm.body)?;
+                case_arms.push((arm.patterns.clone(), body));
+            }
+            Box::new(CaseCommand { word: word.clone(), arms: case_arms })
+        },
+        ast::CompoundCommandKind::Brace(command_list) => {
+            let commands = parse_command_list(command_list)?;
+            Box::new(BraceGroup { commands })
+        },
+    };
+    if compound.io.is_empty() {
+        return Ok(boxed_command);
     }
-    SimpleCommand{ command }
+    Ok(Box::new(RedirectedCompound { command: boxed_command, redirects: compound.io.clone() }))
 }
 
-fn build_redirect_command(args: &[String], redir: &ast::Redirect<ast::TopLevelWord<String>>) -> RedirectCommand {
-    let mut command = process::Command::new(args[0].as_str());
-    for arg in &args[1..] {
-        command.arg(arg.as_str());
-    }
-    let redirect = convert_redirect(redir);
-    RedirectCommand{ command, redirect }
+pub(super) fn parse_command_list(command_list: &[ast::TopLevelCommand<String>]) -> Result<Vec<BoxedExecutable>> {
+    command_list.iter().map(parse_top_level).collect()
 }
 
-fn build_builtin_command(in_args: &[String]) -> Option<BuiltinCommand> {
-    let mut args: Vec<String> = vec![];
-    if let Some(builtin) = in_args.first() {
-        match builtin.as_str() {
-            "cd" | "pwd" | "/bin/pwd" | "exec" | "exit" => {
-                for arg in in_args {
-                    args.push(arg.clone());
-                }
-                Some(BuiltinCommand{ args })
-            },
-            _ => None,
-        }
-    }
-    else {
-        None
-    }
+fn build_loop(guard_body: &ast::GuardBodyPair<ast::TopLevelCommand<String>>, until: bool) -> Result<LoopCommand> {
+    let guard = parse_command_list(&guard_body.guard)?;
+    let body = parse_command_list(&guard_body.body)?;
+    Ok(LoopCommand { guard, body, until })
 }
 
-fn parse_complex_word(complex_word: &ComplexWordAlias) -> String {
-    let string_word: String;
-    match complex_word {
-        ast::ComplexWord::Concat(_word_list) => {
-            eprintln!("Concatenated lists of words aren't supported");
-            return String::from("");
-        },
-        ast::ComplexWord::Single(word) => {
-            match word {
-                ast::Word::DoubleQuoted(_word_list) => {
-                    eprintln!("Double quotes aren't supported");
-                    return String::from("");
-                },
-                ast::Word::SingleQuoted(_word_list) => {
-                    eprintln!("Single quotes aren't supported");
-                    return String::from("");
-                },
-                ast::Word::Simple(simple_word) => {
-                    match simple_word {
-                        ast::SimpleWord::Literal(lit) => { string_word = lit.clone() },
-                        ast::SimpleWord::Escaped(esc) => { string_word = esc.clone() },
-                        ast::SimpleWord::Subst(param_sub)   => {
-                            string_word = convert_subst_to_string(param_sub);
-                        }
-                        _ => {
-                                eprintln!("Unsupported literal");
-                                return String::from("");
-                            },
-                    }
-                },
-            }
+fn build_simple(simple: &SimpleCommandAlias) -> SimpleCommand {
+    let mut env_vars = vec![];
+    let mut words = vec![];
+    let mut redirects = vec![];
+    for item in &simple.redirects_or_env_vars {
+        match item {
+            ast::RedirectOrEnvVar::Redirect(redir) => redirects.push(redir.clone()),
+            ast::RedirectOrEnvVar::EnvVar(name, value) => env_vars.push((name.clone(), value.clone())),
         }
     }
-    string_word
-}
-
-fn convert_subst_to_string(parameter: &ParameterAlias) -> String {
-    let mut string = String::from("");
-    match parameter {
-        ast::ParameterSubstitution::Command(vec_commands) => {
-            for command in vec_commands {
-                let executable_result = parse_top_level(command);
-                let res_string = match executable_result {
-                    Err(e)         => { eprintln!("Error parsing command substitution: {}", e); String::from("") }
-                    Ok(mut executable) => match executable.execute_to_string() {
-                        Ok(string) => string,
-                        Err(e)     => { eprintln!("Error parsing command substitution: {}", e); String::from("") }
-                    }
-                };
-                string.push_str(res_string.trim());
-            }
-        },
-        _ => eprintln!("Unsupported parameter substitution"),
-    }
-    string
-}
-
-fn convert_redirect(redir: &ast::Redirect<ast::TopLevelWord<String>>) -> Redirect {
-    match redir {
-        ast::Redirect::Read(fd, dest) => {
-            let filename = parse_complex_word(dest);
-            Redirect::Read(*fd, filename)
-        }
-        ast::Redirect::Write(fd, dest) => {
-            let filename = parse_complex_word(dest);
-            Redirect::Write(*fd, filename)
-        }
-        ast::Redirect::Append(fd, dest) => {
-            let filename = parse_complex_word(dest);
-            Redirect::Append(*fd, filename)
-        }
-        ast::Redirect::ReadWrite(fd, dest) => {
-            let filename = parse_complex_word(dest);
-            Redirect::ReadWrite(*fd, filename)
-        }
-        ast::Redirect::Clobber(_, _com) => todo!(),
-        ast::Redirect::Heredoc(_, _com) => todo!(),
-        ast::Redirect::DupRead(_, _com) => todo!(),
-        ast::Redirect::DupWrite(_lhs, _rhs) => {
-            todo!("Implement FD dup");
+    for item in &simple.redirects_or_cmd_words {
+        match item {
+            ast::RedirectOrCmdWord::Redirect(redir) => redirects.push(redir.clone()),
+            ast::RedirectOrCmdWord::CmdWord(word) => words.push(word.clone()),
         }
     }
+    SimpleCommand{ env_vars, words, redirects, stdin: None, stdout: None }
 }
-
-// ********************************************
-// Decompose conch_parser's AST structure into useable type aliases
-// ********************************************
-type ListableCommandAlias = ast::ListableCommand<PipeableCommandAlias>;
-
-type PipeableCommandAlias =
-    ast::PipeableCommand
-        <String,
-        Box<SimpleCommandAlias>,
-        Box<CompoundCommandAlias>,
-        std::rc::Rc<CompoundCommandAlias>>;
-
-type SimpleCommandAlias =
-    ast::SimpleCommand<String, ast::TopLevelWord<String>, ast::Redirect<ast::TopLevelWord<String>>>;
-
-type CompoundCommandAlias =
-    ast::CompoundCommand<ast::CompoundCommandKind<String, ast::TopLevelWord<String>, ast::TopLevelCommand<String>>, ast::Redirect<ast::TopLevelWord<String>>>;
-
-type ComplexWordAlias =
-    ast::ComplexWord<ast::Word<String, ast::SimpleWord<String, ast::Parameter<String>, Box<ast::ParameterSubstitution<ast::Parameter<String>, ast::TopLevelWord<String>, ast::TopLevelCommand<String>, ast::Arithmetic<String>>>>>>;
-
-type ParameterAlias =
-    ast::ParameterSubstitution<ast::Parameter<String>, ast::TopLevelWord<String>, ast::TopLevelCommand<String>, ast::Arithmetic<String>>;
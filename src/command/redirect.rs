@@ -0,0 +1,328 @@
+use super::{Redirect, RedirectAlias, TopLevelWordAlias};
+use super::rewrite::HERE_STRING;
+use crate::execute::{Execute, Executor, SHELL_FD_BASE, execute_or_report, move_fd_high};
+use crate::expand;
+use conch_parser::ast;
+use std::env;
+use std::ffi::OsStr;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::os::fd::{AsRawFd, FromRawFd, IntoRawFd, OwnedFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::os::unix::ffi::OsStrExt;
+use std::process::{self, ExitStatus};
+use nix::fcntl::{fcntl, FcntlArg, FdFlag};
+use nix::unistd::{close, dup2};
+use nix::errno::Errno;
+use anyhow::{anyhow, Result};
+
+/// The most a here-document can hold and still be written to a pipe
+/// without blocking, before anything reads from it
+const PIPE_BUFFER_SIZE: usize = 4096;
+
+/// Keeps the names of here-document temporary files apart
+static HEREDOC_COUNT: AtomicU32 = AtomicU32::new(0);
+
+impl Redirect {
+    /// The fd a redirect changes
+    pub fn target(&self) -> RawFd {
+        let (fd, default) = match self {
+            Redirect::Read(fd, _)      => (fd, 0),
+            Redirect::Write(fd, _)     => (fd, 1),
+            Redirect::WriteNew(fd, _)  => (fd, 1),
+            Redirect::Append(fd, _)    => (fd, 1),
+            Redirect::ReadWrite(fd, _) => (fd, 0),
+            Redirect::DupRead(fd, _)   => (fd, 0),
+            Redirect::DupWrite(fd, _)  => (fd, 1),
+            Redirect::Clobber(fd, _)   => (fd, 1),
+            Redirect::Heredoc(fd, _)   => (fd, 0),
+        };
+        fd.map_or(default, RawFd::from)
+    }
+
+    /// Opens the file a redirect names, or works out which fd it copies
+    pub fn resolve(&self) -> Result<FdAction> {
+        let target = self.target();
+        let (filename, options) = match self {
+            Redirect::Read(_, filename) => {
+                (filename, OpenOptions::new().read(true).clone())
+            },
+            Redirect::Write(_, filename) | Redirect::Clobber(_, filename) => {
+                (filename, OpenOptions::new().create(true).truncate(true).write(true).clone())
+            },
+            Redirect::WriteNew(_, filename) => (filename, no_clobber_options(filename)?),
+            Redirect::Append(_, filename) => {
+                (filename, OpenOptions::new().create(true).append(true).clone())
+            },
+            Redirect::ReadWrite(_, filename) => {
+                (filename, OpenOptions::new().create(true).read(true).write(true).clone())
+            },
+            Redirect::DupRead(_, word) | Redirect::DupWrite(_, word) => {
+                //`<&-` and `>&-` close the fd
+                let source = match word.as_str() {
+                    "-"  => None,
+                    word => Some(word.parse::<u16>().map_err(|_| anyhow!("{}: ambiguous redirect", word))?.into()),
+                };
+                return Ok(FdAction{ target, source, file: None });
+            },
+            Redirect::Heredoc(_, body) => {
+                let file = move_fd_high(here_document(body)?)?;
+                return Ok(FdAction{ target, source: Some(file.as_raw_fd()), file: Some(file) });
+            },
+        };
+        let file = options.open(filename).map_err(|e| match e.kind() {
+            io::ErrorKind::AlreadyExists => anyhow!("{}: cannot overwrite existing file", filename.to_string_lossy()),
+            _ => anyhow!("{}: {}", filename.to_string_lossy(), e),
+        })?;
+        //opened out of the way, so applying one redirect can't clobber the
+        //file another is about to copy from
+        let file = move_fd_high(file.into())?;
+        Ok(FdAction{ target, source: Some(file.as_raw_fd()), file: Some(file) })
+    }
+}
+
+/// How to open the file of a `>` redirect under noclobber. A regular file
+/// that exists is refused, but other files such as `/dev/null` can still
+/// be written to. Creating the file exclusively closes the gap between
+/// checking for it and opening it.
+fn no_clobber_options(filename: &OsStr) -> Result<OpenOptions> {
+    match fs::metadata(filename) {
+        Ok(metadata) if metadata.is_file() => Err(anyhow!("{}: cannot overwrite existing file", filename.to_string_lossy())),
+        Ok(_)  => Ok(OpenOptions::new().write(true).clone()),
+        Err(_) => Ok(OpenOptions::new().write(true).create_new(true).clone()),
+    }
+}
+
+/// Makes an fd that reads back the body of a here-document. A body that
+/// fits in a pipe's buffer is written to a pipe, and a longer one to a
+/// temporary file that is deleted straight away.
+fn here_document(body: &OsStr) -> Result<OwnedFd> {
+    if body.len() <= PIPE_BUFFER_SIZE {
+        let (reader, mut writer) = io::pipe()?;
+        writer.write_all(body.as_bytes())?;
+        return Ok(reader.into());
+    }
+    let mut file = loop {
+        let count = HEREDOC_COUNT.fetch_add(1, Ordering::Relaxed);
+        let path = env::temp_dir().join(format!("arsh-heredoc-{}-{}", process::id(), count));
+        match OpenOptions::new().read(true).write(true).create_new(true).open(&path) {
+            Ok(file) => {
+                let _ = fs::remove_file(&path);
+                break file;
+            },
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(anyhow!("here-document: {}", e)),
+        }
+    };
+    file.write_all(body.as_bytes())?;
+    file.seek(SeekFrom::Start(0))?;
+    Ok(file.into())
+}
+
+/// A redirect ready to be applied: `target` becomes a copy of `source`,
+/// or is closed if there is no source
+pub struct FdAction {
+    target: RawFd,
+    source: Option<RawFd>,
+    /// The file opened for the redirect, which has to stay open until it
+    /// has been copied
+    file: Option<OwnedFd>,
+}
+
+impl FdAction {
+    /// Makes the change to this process's fds
+    fn apply(self) -> nix::Result<()> {
+        match self.source {
+            Some(source) if source == self.target => {
+                //dup2 would leave the fd close-on-exec, and the file open
+                //on it mustn't be closed when dropped
+                fcntl(source, FcntlArg::F_SETFD(FdFlag::empty()))?;
+                if let Some(file) = self.file {
+                    let _ = file.into_raw_fd();
+                }
+            },
+            Some(source) => { dup2(source, self.target)?; },
+            None         => { let _ = close(self.target); },
+        }
+        Ok(())
+    }
+}
+
+/// Resolves redirects and sets them up to be applied in `command`'s child,
+/// between fork and exec. The files they opened are returned, and have to
+/// be kept open until the command has been spawned.
+pub fn redirect_command(command: &mut process::Command, redirects: &[Redirect]) -> Result<Vec<OwnedFd>> {
+    let mut steps: Vec<(RawFd, Option<RawFd>)> = vec![];
+    let mut files = vec![];
+    for redirect in redirects {
+        let action = redirect.resolve()?;
+        //report a bad source fd here, where the error can name it
+        if let Some(source) = action.source {
+            let open = match steps.iter().rev().find(|(target, _)| *target == source) {
+                Some((_, earlier)) => earlier.is_some(),
+                None => fcntl(source, FcntlArg::F_GETFD).is_ok(),
+            };
+            if !open {
+                return Err(anyhow!("{}: Bad file descriptor", source));
+            }
+        }
+        steps.push((action.target, action.source));
+        files.extend(action.file);
+    }
+    unsafe {
+        command.pre_exec(move || {
+            for &(target, source) in &steps {
+                //the parent owns the files, so the child leaves them be
+                FdAction{ target, source, file: None }.apply()?;
+            }
+            Ok(())
+        });
+    }
+    Ok(files)
+}
+
+/// Redirects applied to the shell's own fds, for a builtin or function.
+/// The original fds are put back when it is dropped.
+pub struct RedirectGuard {
+    /// Each redirected fd with a copy of what it was before, or `None` if
+    /// it wasn't open
+    saved: Vec<(RawFd, Option<OwnedFd>)>,
+}
+
+impl RedirectGuard {
+    /// Applies redirects left to right, so a later redirect of the same fd
+    /// wins. If one fails, those already applied are undone.
+    pub fn apply(redirects: &[Redirect]) -> Result<RedirectGuard> {
+        let mut guard = RedirectGuard{ saved: vec![] };
+        flush_std();
+        for redirect in redirects {
+            let target = redirect.target();
+            guard.save(target);
+            let action = redirect.resolve()?;
+            let source = action.source;
+            action.apply().map_err(|e| match e {
+                Errno::EBADF => anyhow!("{}: Bad file descriptor", source.unwrap_or(target)),
+                e            => anyhow!("{}: {}", source.unwrap_or(target), e),
+            })?;
+        }
+        Ok(guard)
+    }
+
+    /// Leaves the redirects in place for good, as `exec` does when given
+    /// nothing but redirects
+    pub fn persist(mut self) {
+        self.saved.clear();
+    }
+
+    fn save(&mut self, fd: RawFd) {
+        if self.saved.iter().any(|(saved_fd, _)| *saved_fd == fd) {
+            return;
+        }
+        //the copy is close-on-exec, so commands run meanwhile don't see it
+        let copy = fcntl(fd, FcntlArg::F_DUPFD_CLOEXEC(SHELL_FD_BASE))
+            .ok()
+            .map(|copy| unsafe { OwnedFd::from_raw_fd(copy) });
+        self.saved.push((fd, copy));
+    }
+}
+
+impl Drop for RedirectGuard {
+    fn drop(&mut self) {
+        flush_std();
+        while let Some((fd, copy)) = self.saved.pop() {
+            match copy {
+                Some(copy) => { let _ = dup2(copy.as_raw_fd(), fd); },
+                None       => { let _ = close(fd); },
+            }
+        }
+    }
+}
+
+/// Writes out anything buffered for the shell's stdout or stderr before
+/// the fd under it changes
+fn flush_std() {
+    let _ = io::stdout().flush();
+    let _ = io::stderr().flush();
+}
+
+/// Expands the targets of redirects, keeping them in the order written
+pub fn convert_redirects(shell: &mut Executor, redirects: &[RedirectAlias]) -> Result<Vec<Redirect>> {
+    redirects.iter()
+        .map(|redirect| convert_redirect(shell, redirect))
+        .collect()
+}
+
+/// Runs a builtin or function with redirects applied to the shell's own
+/// fds. Errors are reported while the redirects are still in place, so
+/// that `2>file` catches them.
+pub fn execute_redirected(command: &mut dyn Execute, redirects: &[Redirect], shell: &mut Executor) -> Result<ExitStatus> {
+    if redirects.is_empty() {
+        return command.execute(shell);
+    }
+    let _guard = RedirectGuard::apply(redirects)?;
+    Ok(execute_or_report(command, shell))
+}
+
+fn convert_redirect(shell: &mut Executor, redir: &RedirectAlias) -> Result<Redirect> {
+    let redirect = match redir {
+        ast::Redirect::Read(fd, dest) => match here_string(dest) {
+            Some(word) => {
+                let mut body = expand::expand_word(shell, &word)?;
+                body.push("\n");
+                Redirect::Heredoc(*fd, body)
+            },
+            None       => Redirect::Read(*fd, expand::expand_word(shell, dest)?),
+        },
+        ast::Redirect::Write(fd, dest) => {
+            let filename = expand::expand_word(shell, dest)?;
+            match shell.options.noclobber {
+                true  => Redirect::WriteNew(*fd, filename),
+                false => Redirect::Write(*fd, filename),
+            }
+        }
+        ast::Redirect::Append(fd, dest) => {
+            let filename = expand::expand_word(shell, dest)?;
+            Redirect::Append(*fd, filename)
+        }
+        ast::Redirect::ReadWrite(fd, dest) => {
+            let filename = expand::expand_word(shell, dest)?;
+            Redirect::ReadWrite(*fd, filename)
+        }
+        ast::Redirect::DupRead(fd, dest) => {
+            let source = expand::expand_word(shell, dest)?;
+            Redirect::DupRead(*fd, source.to_string_lossy().into_owned())
+        }
+        ast::Redirect::DupWrite(fd, dest) => {
+            let source = expand::expand_word(shell, dest)?;
+            Redirect::DupWrite(*fd, source.to_string_lossy().into_owned())
+        }
+        ast::Redirect::Heredoc(fd, body) => {
+            let body = expand::expand_word(shell, body)?;
+            Redirect::Heredoc(*fd, body)
+        }
+        ast::Redirect::Clobber(fd, dest) => {
+            let filename = expand::expand_word(shell, dest)?;
+            Redirect::Clobber(*fd, filename)
+        }
+    };
+    Ok(redirect)
+}
+
+/// The word of a `<<<` here-string, which was rewritten as a `<` redirect
+/// whose word starts with a marker. The marker is looked for before the
+/// word is expanded, so no value it expands to can pass for one.
+fn here_string(word: &TopLevelWordAlias) -> Option<TopLevelWordAlias> {
+    let mut word = word.clone();
+    let first = match &mut word.0 {
+        ast::ComplexWord::Single(part)  => part,
+        ast::ComplexWord::Concat(parts) => parts.first_mut()?,
+    };
+    match first {
+        ast::Word::Simple(ast::SimpleWord::Literal(text)) if text.starts_with(HERE_STRING) => {
+            text.remove(0);
+            Some(word)
+        },
+        _ => None,
+    }
+}
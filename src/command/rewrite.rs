@@ -0,0 +1,206 @@
+use super::scan::{scan, Context, Quotes};
+
+/// Starts the word of a `<` redirect that was written as a `<<<` here-string
+pub const HERE_STRING: char = '\u{E000}';
+
+/// Comes before the command substitution that a `<(cmd)` was written as
+pub const PROCESS_IN: char = '\u{E001}';
+
+/// Comes before the command substitution that a `>(cmd)` was written as
+pub const PROCESS_OUT: char = '\u{E002}';
+
+/// Rewrites syntax that conch-parser doesn't know into forms it does,
+/// before the input is parsed. Markers from Unicode's private use area
+/// carry the original meaning through to execution.
+pub fn rewrite(input: &str) -> String {
+    let input = rewrite_arithmetic_commands(&rewrite_here_strings(input));
+    rewrite_process_substitutions(&input)
+}
+
+/// `cmd <<<word` becomes `cmd <\u{E000}word`
+fn rewrite_here_strings(input: &str) -> String {
+    let mut output = String::with_capacity(input.len());
+    let scan = scan(input);
+    let mut chars = input.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if scan.context(i) == Context::Syntax && input[i..].starts_with("<<<") {
+            chars.next();
+            chars.next();
+            while chars.next_if(|(_, c)| *c == ' ' || *c == '\t').is_some() {}
+            output.push('<');
+            output.push(HERE_STRING);
+            continue;
+        }
+        output.push(c);
+    }
+    output
+}
+
+/// `((expr))` becomes `let "expr"`, which conch-parser would otherwise
+/// read as two nested subshells. The expression is expanded as if double
+/// quoted, just as the inside of `$(( ))` would be, and an empty one
+/// becomes `false`.
+fn rewrite_arithmetic_commands(input: &str) -> String {
+    let mut output = String::with_capacity(input.len());
+    let scan = scan(input);
+    let mut chars = input.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if scan.context(i) == Context::ArithmeticCommand {
+            let mut end = i + c.len_utf8();
+            while let Some((j, c)) = chars.next_if(|(j, _)| scan.context(*j) == Context::ArithmeticCommand) {
+                end = j + c.len_utf8();
+            }
+            //the expression without its two pairs of parentheses
+            let expr = double_quoted(&input[i + 2..end - 2]);
+            match expr.trim().is_empty() {
+                true  => output.push_str("false"),
+                false => {
+                    output.push_str("let \"");
+                    output.push_str(&expr);
+                    output.push('"');
+                },
+            }
+            continue;
+        }
+        output.push(c);
+    }
+    output
+}
+
+/// Rewrites an arithmetic expression to go inside double quotes. Its own
+/// double quotes are removed, single-quoted text is escaped and
+/// substitutions are kept whole.
+fn double_quoted(expr: &str) -> String {
+    let mut output = String::with_capacity(expr.len());
+    let mut i = 0;
+    while let Some(c) = expr[i..].chars().next() {
+        let mut end = i + c.len_utf8();
+        match c {
+            '"' => {},
+            '\\' => {
+                end += expr[end..].chars().next().map_or(0, char::len_utf8);
+                output.push_str(&expr[i..end]);
+            },
+            '\'' => {
+                let len = expr[end..].find('\'').unwrap_or(expr.len() - end);
+                for c in expr[end..end + len].chars() {
+                    if "\\\"$`".contains(c) {
+                        output.push('\\');
+                    }
+                    output.push(c);
+                }
+                end = (end + len + 1).min(expr.len());
+            },
+            '$' | '`' => {
+                end = i + substitution_len(&expr[i..]);
+                output.push_str(&expr[i..end]);
+            },
+            c => output.push(c),
+        }
+        i = end;
+    }
+    output
+}
+
+/// The length of the `$( )`, `${ }` or backtick substitution at the start
+/// of the input, or 1 for a `$` that doesn't start one
+fn substitution_len(input: &str) -> usize {
+    let (start, open, close) = match input.as_bytes().get(..2) {
+        Some(b"$(") => (2, '(', ')'),
+        Some(b"${") => (2, '{', '}'),
+        _ if input.starts_with('`') => (1, '`', '`'),
+        _ => return 1,
+    };
+    let mut quotes = Quotes::default();
+    let mut depth = 0;
+    for (i, c) in input[start..].char_indices() {
+        if !quotes.unquoted(c) {
+            continue;
+        }
+        if c == close && depth == 0 {
+            return start + i + 1;
+        }
+        else if c == close {
+            depth -= 1;
+        }
+        else if c == open {
+            depth += 1;
+        }
+    }
+    input.len()
+}
+
+/// `<(cmd)` becomes `\u{E001}$(cmd)` and `>(cmd)` becomes `\u{E002}$(cmd)`.
+/// Comparisons inside `$(( ))` are left alone.
+fn rewrite_process_substitutions(input: &str) -> String {
+    let mut output = String::with_capacity(input.len());
+    let scan = scan(input);
+    let mut previous = None;
+    for (i, c) in input.char_indices() {
+        let marker = match c {
+            '<' => Some(PROCESS_IN),
+            '>' => Some(PROCESS_OUT),
+            _   => None,
+        };
+        if let Some(marker) = marker {
+            if scan.context(i) == Context::Syntax && input[i + 1..].starts_with('(') && !matches!(previous, Some('<' | '>')) {
+                output.push(marker);
+                output.push('$');
+                previous = Some('$');
+                continue;
+            }
+        }
+        output.push(c);
+        previous = Some(c);
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn here_strings() {
+        assert_eq!(rewrite("cat <<<word"), "cat <\u{E000}word");
+        assert_eq!(rewrite("cat <<<  \"a b\""), "cat <\u{E000}\"a b\"");
+        assert_eq!(rewrite("echo '<<<' \\<<<x"), "echo '<<<' \\<<<x");
+    }
+
+    #[test]
+    fn arithmetic_commands() {
+        assert_eq!(rewrite("((i++))"), "let \"i++\"");
+        assert_eq!(rewrite("if ((x > (1 + 2))); then :; fi"), "if let \"x > (1 + 2)\"; then :; fi");
+        assert_eq!(rewrite("echo $((1 + 2))"), "echo $((1 + 2))");
+        assert_eq!(rewrite("((cd a); (cd b))"), "((cd a); (cd b))");
+        assert_eq!(rewrite("echo '((x))'"), "echo '((x))'");
+        assert_eq!(rewrite("(( \"1\" + x ))"), "let \" 1 + x \"");
+        assert_eq!(rewrite("(( $(echo \"3\") + ${y:-\"1\"} ))"), "let \" $(echo \"3\") + ${y:-\"1\"} \"");
+        assert_eq!(rewrite("(( '$x' ))"), "let \" \\$x \"");
+        assert_eq!(rewrite("(( ))"), "false");
+    }
+
+    #[test]
+    fn process_substitutions() {
+        assert_eq!(rewrite("diff <(a) >(b)"), "diff \u{E001}$(a) \u{E002}$(b)");
+        assert_eq!(rewrite("echo $((1<(2)))"), "echo $((1<(2)))");
+        assert_eq!(rewrite("echo '<(a)' \"<(a)\""), "echo '<(a)' \"<(a)\"");
+        assert_eq!(rewrite("x=\"$(cat <(echo a))\""), "x=\"$(cat \u{E001}$(echo a))\"");
+        assert_eq!(rewrite("x=`cat <(echo a)`"), "x=`cat \u{E001}$(echo a)`");
+    }
+
+    #[test]
+    fn heredoc_bodies_are_left_alone() {
+        let input = "cat <<EOF\nusage: diff <(a) b\n((i++)) x=((1+2)) <<<y\nEOF\necho <(b)\n";
+        let expected = "cat <<EOF\nusage: diff <(a) b\n((i++)) x=((1+2)) <<<y\nEOF\necho \u{E001}$(b)\n";
+        assert_eq!(rewrite(input), expected);
+        let input = "cat <<-'EOF' <<X\n\t$(sort <(a))\n\tEOF\n((i))\nX\n((i))\n";
+        let expected = "cat <<-'EOF' <<X\n\t$(sort <(a))\n\tEOF\n((i))\nX\nlet \"i\"\n";
+        assert_eq!(rewrite(input), expected);
+    }
+
+    #[test]
+    fn comments_are_left_alone() {
+        assert_eq!(rewrite("echo a # don't diff <(a)\necho <(b)"), "echo a # don't diff <(a)\necho \u{E001}$(b)");
+    }
+}
@@ -0,0 +1,301 @@
+use std::collections::VecDeque;
+
+/// What a character of shell input is part of, for the passes that look
+/// at the input before it is parsed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Context {
+    /// Unquoted shell syntax
+    Syntax,
+    /// Quoted or escaped text
+    Quoted,
+    Comment,
+    /// An arithmetic expansion `$((expr))`, apart from its `$`
+    Arithmetic,
+    /// A `((expr))` command, parentheses and all
+    ArithmeticCommand,
+    /// The body of a here-document, including the line that ends it
+    HereDoc,
+}
+
+/// A here-document started with `<<` or `<<-`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HereDoc {
+    /// The delimiter with its quoting removed
+    pub delimiter: String,
+    /// Whether it was given with `<<-`, which strips leading tabs
+    pub strip_tabs: bool,
+}
+
+/// The context of each character of some input
+pub struct Scan {
+    /// Indexed by byte, with every byte of a character in its context
+    contexts: Vec<Context>,
+    /// The here-documents whose bodies the input doesn't finish, in order
+    pub unfinished: Vec<HereDoc>,
+}
+
+impl Scan {
+    /// The context of the character starting at byte `i`
+    pub fn context(&self, i: usize) -> Context {
+        self.contexts[i]
+    }
+}
+
+/// A level of `$( )` or backtick command substitution, inside which
+/// quoting starts over
+#[derive(Default)]
+struct Frame {
+    double: bool,
+    /// Parentheses opened inside the substitution and not yet closed
+    parens: usize,
+    backtick: bool,
+}
+
+/// Works out what each character of the input is part of, following
+/// quotes, comments, command substitutions, arithmetic and the bodies of
+/// here-documents
+pub fn scan(input: &str) -> Scan {
+    let mut contexts = vec![Context::Syntax; input.len()];
+    let mut frames = vec![Frame::default()];
+    let (mut single, mut escaped, mut comment) = (false, false, false);
+    //here-documents whose bodies start after the next unquoted newline
+    let mut heredocs = VecDeque::new();
+    let mut previous = None;
+    let mut i = 0;
+    while let Some(c) = input[i..].chars().next() {
+        let mut end = i + c.len_utf8();
+        let nested = frames.len() > 1;
+        let frame = frames.last_mut().expect("the outermost frame is never popped");
+        let unquoted = !frame.double;
+        let context = if comment && c != '\n' {
+            Context::Comment
+        }
+        else if escaped {
+            escaped = false;
+            Context::Quoted
+        }
+        else if single {
+            single = c != '\'';
+            Context::Quoted
+        }
+        else {
+            comment = false;
+            match c {
+                '\\' => {
+                    escaped = true;
+                    Context::Quoted
+                },
+                '\'' if unquoted => {
+                    single = true;
+                    Context::Quoted
+                },
+                '"' => {
+                    frame.double = !frame.double;
+                    Context::Quoted
+                },
+                '`' => {
+                    match frame.backtick {
+                        true  => { frames.pop(); },
+                        false => frames.push(Frame{ backtick: true, ..Frame::default() }),
+                    }
+                    Context::Quoted
+                },
+                '$' if input[end..].starts_with("((") && arithmetic_len(&input[end + 2..]).is_some() => {
+                    let len = arithmetic_len(&input[end + 2..]).unwrap_or_default();
+                    contexts[end..end + len + 4].fill(Context::Arithmetic);
+                    end += len + 4;
+                    contexts[i] = if unquoted { Context::Syntax } else { Context::Quoted };
+                    previous = Some(')');
+                    i = end;
+                    continue;
+                },
+                '$' if input[end..].starts_with('(') => {
+                    end += 1;
+                    frames.push(Frame::default());
+                    if unquoted { Context::Syntax } else { Context::Quoted }
+                },
+                //`((` that isn't part of `$((`, `<((` or `>((` starts a command
+                '(' if unquoted && input[end..].starts_with('(') && !matches!(previous, Some('$' | '<' | '>'))
+                    && arithmetic_len(&input[end + 1..]).is_some() => {
+                    end += arithmetic_len(&input[end + 1..]).unwrap_or_default() + 3;
+                    Context::ArithmeticCommand
+                },
+                '(' if unquoted => {
+                    frame.parens += 1;
+                    Context::Syntax
+                },
+                ')' if unquoted => {
+                    if frame.parens > 0 {
+                        frame.parens -= 1;
+                    }
+                    else if nested && !frame.backtick {
+                        frames.pop();
+                    }
+                    Context::Syntax
+                },
+                '#' if unquoted && previous.is_none_or(|p: char| p.is_whitespace() || ";&|()<>".contains(p)) => {
+                    comment = true;
+                    Context::Comment
+                },
+                //`<<` but not part of a `<<<` here-string
+                '<' if unquoted && input[end..].starts_with('<') && !input[end..].starts_with("<<")
+                    && previous != Some('<') => {
+                    let (heredoc, len) = heredoc_operator(&input[end + 1..]);
+                    heredocs.push_back(heredoc);
+                    end += len + 1;
+                    Context::Syntax
+                },
+                _ if unquoted => Context::Syntax,
+                _ => Context::Quoted,
+            }
+        };
+        contexts[i..end].fill(context);
+        previous = input[..end].chars().next_back();
+        i = end;
+        if c == '\n' && context == Context::Syntax {
+            i = heredoc_bodies(input, i, &mut heredocs, &mut contexts);
+        }
+    }
+    Scan{ contexts, unfinished: heredocs.into() }
+}
+
+/// Reads what follows a `<<`: an optional `-` and the delimiter word, whose
+/// quoting is removed. Returns the here-document and the length read.
+fn heredoc_operator(input: &str) -> (HereDoc, usize) {
+    let strip_tabs = input.starts_with('-');
+    let mut chars = input.char_indices().skip(strip_tabs as usize).peekable();
+    while chars.next_if(|(_, c)| *c == ' ' || *c == '\t').is_some() {}
+    let mut delimiter = String::new();
+    let (mut single, mut double) = (false, false);
+    while let Some(&(i, c)) = chars.peek() {
+        match c {
+            '\'' if !double => single = !single,
+            '"' if !single  => double = !double,
+            '\\' if !single => {
+                chars.next();
+                delimiter.extend(chars.peek().map(|(_, c)| *c));
+            },
+            c if !single && !double && (c.is_whitespace() || ";&|<>()".contains(c)) => {
+                return (HereDoc{ delimiter, strip_tabs }, i);
+            },
+            c => delimiter.push(c),
+        }
+        chars.next();
+    }
+    (HereDoc{ delimiter, strip_tabs }, input.len())
+}
+
+/// Marks the lines from `start` as the bodies of the pending here-documents,
+/// in order, returning where the last one that ends does
+fn heredoc_bodies(input: &str, start: usize, heredocs: &mut VecDeque<HereDoc>, contexts: &mut [Context]) -> usize {
+    let mut i = start;
+    while let Some(heredoc) = heredocs.front() {
+        if i == input.len() {
+            break;
+        }
+        let end = input[i..].find('\n').map_or(input.len(), |n| i + n + 1);
+        contexts[i..end].fill(Context::HereDoc);
+        let line = input[i..end].strip_suffix('\n').unwrap_or(&input[i..end]);
+        let line = if heredoc.strip_tabs { line.trim_start_matches('\t') } else { line };
+        if line == heredoc.delimiter {
+            heredocs.pop_front();
+        }
+        i = end;
+    }
+    i
+}
+
+/// The length of an arithmetic expression up to the `))` that closes it,
+/// or `None` if it isn't closed
+fn arithmetic_len(input: &str) -> Option<usize> {
+    let mut quotes = Quotes::default();
+    let mut depth = 0;
+    for (i, c) in input.char_indices() {
+        if !quotes.unquoted(c) {
+            continue;
+        }
+        match c {
+            '(' => depth += 1,
+            ')' if depth == 0 => return input[i..].starts_with("))").then_some(i),
+            ')' => depth -= 1,
+            _   => {},
+        }
+    }
+    None
+}
+
+/// Tracks quoting while scanning an arithmetic expression or substitution
+/// a character at a time
+#[derive(Default)]
+pub(super) struct Quotes {
+    single: bool,
+    double: bool,
+    escaped: bool,
+}
+
+impl Quotes {
+    /// Takes in the next character, returning whether it is unquoted
+    pub(super) fn unquoted(&mut self, c: char) -> bool {
+        if self.escaped {
+            self.escaped = false;
+            return false;
+        }
+        match c {
+            '\'' if !self.double => self.single = !self.single,
+            '"' if !self.single  => self.double = !self.double,
+            '\\' if !self.single => self.escaped = true,
+            _ => return !self.single && !self.double,
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn delimiters(input: &str) -> Vec<String> {
+        scan(input).unfinished.into_iter().map(|heredoc| heredoc.delimiter).collect()
+    }
+
+    #[test]
+    fn finds_heredoc_delimiters() {
+        assert_eq!(delimiters("cat <<EOF\n"), ["EOF"]);
+        assert_eq!(delimiters("cat <<'E F' <<\"G\"\n"), ["E F", "G"]);
+        assert_eq!(scan("cat <<-\\EOF\n").unfinished, [HereDoc{ delimiter: String::from("EOF"), strip_tabs: true }]);
+    }
+
+    #[test]
+    fn finished_heredocs_are_not_pending() {
+        assert!(delimiters("cat <<EOF\nbody\nEOF\n").is_empty());
+        assert!(delimiters("cat <<-EOF\n\tbody\n\tEOF\n").is_empty());
+        assert_eq!(delimiters("cat <<A <<B\na\nA\nb\n"), ["B"]);
+    }
+
+    #[test]
+    fn shifts_and_here_strings_are_not_heredocs() {
+        assert!(delimiters("echo $((1<<2))\n").is_empty());
+        assert!(delimiters("((x<<=1))\n").is_empty());
+        assert!(delimiters("cat <<<word\n").is_empty());
+        assert!(delimiters("echo '<<EOF' \"<<EOF\" \\<<EOF\n").is_empty());
+        assert!(delimiters("echo hi # cat <<EOF\n").is_empty());
+    }
+
+    #[test]
+    fn heredoc_in_command_substitution() {
+        assert_eq!(delimiters("x=\"$(cat <<EOF\n"), ["EOF"]);
+    }
+
+    #[test]
+    fn marks_contexts() {
+        let input = "a '<' $((1)) ((2)) # c\nx <<E\n<(\nE\n";
+        let scan = scan(input);
+        let at = |text: &str| scan.context(input.find(text).unwrap());
+        assert_eq!(at("a"), Context::Syntax);
+        assert_eq!(at("<'"), Context::Quoted);
+        assert_eq!(at("((1"), Context::Arithmetic);
+        assert_eq!(at("((2"), Context::ArithmeticCommand);
+        assert_eq!(at("c\n"), Context::Comment);
+        assert_eq!(at("<("), Context::HereDoc);
+    }
+}
@@ -0,0 +1,345 @@
+use crate::execute::{exit_status, status_code, signals};
+use std::process::ExitStatus;
+use std::os::unix::process::ExitStatusExt;
+use nix::unistd::{self, Pid};
+use nix::sys::wait::WaitStatus;
+use nix::sys::signal::{self, Signal};
+use nix::errno::Errno;
+use anyhow::{anyhow, Result};
+
+/// The descriptor job control hands between process groups
+const TERMINAL: i32 = 0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobState {
+    Running,
+    Stopped,
+    Done(ExitStatus),
+}
+
+#[derive(Debug)]
+pub struct Process {
+    pub pid: Pid,
+    pub state: JobState,
+}
+
+#[derive(Debug)]
+pub struct Job {
+    pub id: usize,
+    /// The process group the job runs in, when job control is on
+    pub pgid: Option<Pid>,
+    pub processes: Vec<Process>,
+    /// The command as the user typed it, for display
+    pub command: String,
+    /// Whether the job has stopped since the user was last told about it
+    notify: bool,
+}
+
+/// Commands the shell started without waiting for them, or which were
+/// stopped while in the foreground, numbered from 1. The most recently
+/// added job is the current job, `%+`, and the one before it the
+/// previous job, `%-`.
+#[derive(Debug, Default)]
+pub struct JobTable {
+    jobs: Vec<Job>,
+}
+
+impl Job {
+    pub fn new(pgid: Option<Pid>, pids: Vec<Pid>, command: String) -> Self {
+        let processes = pids.into_iter().map(|pid| Process { pid, state: JobState::Running }).collect();
+        Job { id: 0, pgid, processes, command, notify: false }
+    }
+
+    /// A job is stopped if any of its processes are, and done once all of
+    /// them are, taking the status of the last
+    pub fn state(&self) -> JobState {
+        if self.processes.iter().any(|process| process.state == JobState::Stopped) {
+            return JobState::Stopped;
+        }
+        if self.processes.iter().any(|process| process.state == JobState::Running) {
+            return JobState::Running;
+        }
+        match self.processes.last() {
+            Some(process) => process.state,
+            None          => JobState::Done(exit_status(0)),
+        }
+    }
+
+    /// Waits until every process in the job has finished or stopped
+    pub fn wait(&mut self) -> Result<()> {
+        for process in &mut self.processes {
+            while process.state == JobState::Running {
+                process.wait()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Waits for the job with its process group owning the terminal, then
+    /// takes the terminal back for the shell
+    pub fn wait_in_foreground(&mut self) -> Result<()> {
+        if let Some(pgid) = self.pgid {
+            give_terminal_to(pgid);
+        }
+        let result = self.wait();
+        if self.pgid.is_some() {
+            give_terminal_to(unistd::getpgrp());
+            //the terminal echoed ^C without a newline
+            if self.status().signal() == Some(Signal::SIGINT as i32) {
+                println!();
+            }
+        }
+        result
+    }
+
+    /// Sends a signal to every process in the job
+    pub fn signal(&self, signal: Signal) -> Result<()> {
+        match self.pgid {
+            Some(pgid) => signal::killpg(pgid, signal)?,
+            None => {
+                for process in self.processes.iter().filter(|process| !matches!(process.state, JobState::Done(_))) {
+                    signal::kill(process.pid, signal)?;
+                }
+            },
+        }
+        Ok(())
+    }
+
+    /// Sends SIGCONT to the job and marks its stopped processes as running
+    pub fn resume(&mut self) -> Result<()> {
+        self.signal(Signal::SIGCONT)?;
+        for process in &mut self.processes {
+            if process.state == JobState::Stopped {
+                process.state = JobState::Running;
+            }
+        }
+        Ok(())
+    }
+
+    /// The status of a finished job, or of a stopped one as seen through `$?`
+    pub fn status(&self) -> ExitStatus {
+        match self.state() {
+            JobState::Done(status) => status,
+            JobState::Stopped      => exit_status(128 + Signal::SIGTSTP as i32),
+            JobState::Running      => exit_status(0),
+        }
+    }
+}
+
+impl Process {
+    fn wait(&mut self) -> Result<()> {
+        match signals::wait(self.pid) {
+            Ok(status) => self.update(status),
+            Err(Errno::ECHILD) => self.state = JobState::Done(exit_status(0)),
+            Err(e) => return Err(e.into()),
+        }
+        Ok(())
+    }
+
+    fn update(&mut self, status: WaitStatus) {
+        match status {
+            WaitStatus::Exited(_pid, code) => self.state = JobState::Done(exit_status(code)),
+            WaitStatus::Signaled(_pid, signal, _) => {
+                self.state = JobState::Done(ExitStatus::from_raw(signal as i32));
+            },
+            WaitStatus::Stopped(_pid, _signal) => self.state = JobState::Stopped,
+            WaitStatus::Continued(_pid) => self.state = JobState::Running,
+            _ => {},
+        }
+    }
+}
+
+impl JobTable {
+    /// Records a new job and returns its job ID
+    pub fn add(&mut self, mut job: Job) -> usize {
+        job.id = self.jobs.iter().map(|job| job.id).max().unwrap_or(0) + 1;
+        let id = job.id;
+        self.jobs.push(job);
+        id
+    }
+
+    pub fn get_mut(&mut self, id: usize) -> Result<&mut Job> {
+        self.jobs.iter_mut()
+            .find(|job| job.id == id)
+            .ok_or_else(|| anyhow!("%{}: no such job", id))
+    }
+
+    pub fn remove(&mut self, id: usize) -> Option<Job> {
+        let index = self.jobs.iter().position(|job| job.id == id)?;
+        Some(self.jobs.remove(index))
+    }
+
+    /// Collects the status of any job process that has changed, without blocking
+    pub fn reap(&mut self) {
+        for job in &mut self.jobs {
+            let before = job.state();
+            for process in &mut job.processes {
+                while let Some(status) = signals::try_wait(process.pid) {
+                    process.update(status);
+                }
+            }
+            if job.state() == JobState::Stopped && before != JobState::Stopped {
+                job.notify = true;
+            }
+        }
+        let pids: Vec<Pid> = self.jobs.iter()
+            .flat_map(|job| job.processes.iter().map(|process| process.pid))
+            .collect();
+        signals::discard_except(&pids);
+    }
+
+    /// Prints a line for every job that finished or stopped since the last
+    /// report, removing the finished ones from the table
+    pub fn report_changes(&mut self) {
+        let count = self.jobs.len();
+        for (i, job) in self.jobs.iter_mut().enumerate() {
+            match job.state() {
+                JobState::Done(status) => println!("{}", job_line(job, marker(i, count), &status_text(&status))),
+                JobState::Stopped if job.notify => {
+                    println!("{}", job_line(job, marker(i, count), "Stopped"));
+                    job.notify = false;
+                },
+                _ => {},
+            }
+        }
+        self.jobs.retain(|job| !matches!(job.state(), JobState::Done(_)));
+    }
+
+    /// Prints every job with its state, as the `jobs` builtin does. Finished
+    /// jobs are listed one last time and then removed.
+    pub fn list(&mut self) {
+        let count = self.jobs.len();
+        for (i, job) in self.jobs.iter_mut().enumerate() {
+            let line = match job.state() {
+                JobState::Running      => format!("{} &", job_line(job, marker(i, count), "Running")),
+                JobState::Stopped      => job_line(job, marker(i, count), "Stopped"),
+                JobState::Done(status) => job_line(job, marker(i, count), &status_text(&status)),
+            };
+            println!("{}", line);
+            job.notify = false;
+        }
+        self.jobs.retain(|job| !matches!(job.state(), JobState::Done(_)));
+    }
+
+    /// Waits for every process in a job to finish, removes it and returns its status
+    pub fn wait(&mut self, id: usize) -> Result<ExitStatus> {
+        let job = self.get_mut(id)?;
+        for process in &mut job.processes {
+            while !matches!(process.state, JobState::Done(_)) {
+                process.wait()?;
+            }
+        }
+        let status = job.status();
+        self.remove(id);
+        Ok(status)
+    }
+
+    /// The IDs of every job, oldest first
+    pub fn ids(&self) -> Vec<usize> {
+        self.jobs.iter().map(|job| job.id).collect()
+    }
+
+    /// Resolves a job spec: `%n`, `%%`, `%+` and `%-`, `%name` for the job
+    /// whose command starts with name, or the pid of one of a job's processes
+    pub fn find(&self, spec: &str) -> Result<usize> {
+        let job = match spec.strip_prefix('%') {
+            Some("%") | Some("+") | Some("") => self.jobs.last(),
+            Some("-") => self.jobs.iter().rev().nth(1),
+            Some(id) if id.parse::<usize>().is_ok() => {
+                let id = id.parse::<usize>()?;
+                self.jobs.iter().find(|job| job.id == id)
+            },
+            Some(prefix) => self.jobs.iter().rev().find(|job| job.command.starts_with(prefix)),
+            None => {
+                let pid = spec.parse::<i32>().map_err(|_| anyhow!("{}: not a pid or valid job spec", spec))?;
+                self.jobs.iter().find(|job| job.processes.iter().any(|process| process.pid.as_raw() == pid))
+            },
+        };
+        match job {
+            Some(job) => Ok(job.id),
+            None      => Err(anyhow!("{}: no such job", spec)),
+        }
+    }
+
+    /// The job a builtin like `fg` acts on: the one named by its first
+    /// argument, or the current job
+    pub fn find_or_current(&self, args: &[String]) -> Result<usize> {
+        match args.get(1) {
+            Some(spec) => self.find(spec),
+            None       => self.find("%+").map_err(|_| anyhow!("current: no such job")),
+        }
+    }
+
+    /// Makes a job that was stopped in the foreground the current job and
+    /// tells the user about it
+    pub fn report_stopped(&mut self, id: usize) {
+        if let Some(job) = self.remove(id) {
+            println!();
+            println!("{}", job_line(&job, '+', "Stopped"));
+            self.jobs.push(job);
+        }
+    }
+}
+
+/// Stops the shell until it is in the foreground, if it was started in
+/// the background of another shell
+pub fn wait_for_foreground() {
+    if !unistd::isatty(TERMINAL).unwrap_or(false) {
+        return;
+    }
+    while let Ok(owner) = unistd::tcgetpgrp(TERMINAL) {
+        if owner == unistd::getpgrp() {
+            break;
+        }
+        let _ = signal::killpg(unistd::getpgrp(), Signal::SIGTTIN);
+    }
+}
+
+/// Puts an interactive shell in its own process group in control of the
+/// terminal. The shell must already be ignoring the signals that would
+/// stop it while doing so. Returns false if stdin isn't a terminal.
+pub fn init_job_control() -> bool {
+    if !unistd::isatty(TERMINAL).unwrap_or(false) {
+        return false;
+    }
+    let pid = unistd::getpid();
+    if unistd::setpgid(pid, pid).is_err() && unistd::getpgrp() != pid {
+        return false;
+    }
+    give_terminal_to(pid);
+    true
+}
+
+fn give_terminal_to(pgid: Pid) {
+    let _ = unistd::tcsetpgrp(TERMINAL, pgid);
+}
+
+/// `+` for the current job and `-` for the previous one
+fn marker(index: usize, count: usize) -> char {
+    match count - index {
+        1 => '+',
+        2 => '-',
+        _ => ' ',
+    }
+}
+
+fn job_line(job: &Job, marker: char, state: &str) -> String {
+    format!("[{}]{}  {:<24}{}", job.id, marker, state, job.command)
+}
+
+fn status_text(status: &ExitStatus) -> String {
+    if let Some(signal) = status.signal() {
+        return match Signal::try_from(signal) {
+            Ok(Signal::SIGTERM) => String::from("Terminated"),
+            Ok(Signal::SIGKILL) => String::from("Killed"),
+            Ok(Signal::SIGINT)  => String::from("Interrupt"),
+            Ok(Signal::SIGHUP)  => String::from("Hangup"),
+            Ok(signal)          => String::from(signal.as_str()),
+            Err(_)              => format!("Signal {}", signal),
+        };
+    }
+    match status_code(status) {
+        0    => String::from("Done"),
+        code => format!("Exit {}", code),
+    }
+}
@@ -1,18 +1,41 @@
-use crate::command::{TopLevelCommandList};
+pub mod variables;
+pub mod jobs;
+pub mod signals;
+pub mod traps;
+pub mod options;
+
+use crate::expand;
+use crate::command::{TopLevelCommandList, CompoundCommandAlias};
 use std::process::{self, ExitStatus};
+use std::os::unix::process::CommandExt;
 use std::os::unix::process::ExitStatusExt;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::io::{self, Read, Write, PipeReader, PipeWriter};
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::mem;
+use nix::unistd::{ForkResult, Pid};
+use nix::sys::wait::WaitStatus;
+use nix::sys::signal::Signal;
+use nix::fcntl::{fcntl, FcntlArg, FdFlag};
 use anyhow::{Result};
 use std::fmt;
+use std::env;
+use std::ffi::OsString;
+use variables::Variables;
+use jobs::{Job, JobState, JobTable};
+use traps::Traps;
+use options::Options;
 
 pub trait Execute {
-    fn execute(&mut self) -> Result<ExitStatus>;
-    fn execute_to_string(&mut self) -> Result<String>;
+    fn execute(&mut self, shell: &mut Executor) -> Result<ExitStatus>;
 }
 
 pub trait Pipe: Execute {
-    fn get_child(&mut self) -> std::process::Child;
-    fn pipe_in(&mut self, in_pipe: process::ChildStdout);
-    fn pipe_out(&mut self);
+    /// Starts the command without waiting for it to finish
+    fn get_child(&mut self, shell: &mut Executor) -> Result<Pid>;
+    fn pipe_in(&mut self, in_pipe: PipeReader);
+    fn pipe_out(&mut self, out_pipe: PipeWriter);
 }
 
 #[derive(Debug, Clone)]
@@ -28,22 +51,364 @@ impl fmt::Display for UnrecognizedCommandError {
     }
 }
 
+/// State owned by the running shell that commands read and modify
+/// while they execute.
 #[derive(Debug)]
 pub struct Executor {
     pub history: Vec<TopLevelCommandList>,
     pub last_status: ExitStatus,
+    pub variables: Variables,
+    /// `$0`, the name of the shell or script being run
+    pub arg0: OsString,
+    /// `$1`, `$2`, ...
+    pub positional: Vec<OsString>,
+    /// `$$`, which subshells inherit from the shell that started them
+    pub pid: u32,
+    /// `$!`, the most recent background command
+    pub last_bg_pid: Option<u32>,
+    /// A `break` or `continue` waiting to be handled by an enclosing loop
+    pub control_flow: Option<ControlFlow>,
+    /// How many loops are currently running
+    pub loop_depth: u32,
+    pub functions: HashMap<String, Rc<CompoundCommandAlias>>,
+    /// How many function calls are currently running
+    pub function_depth: u32,
+    pub jobs: JobTable,
+    /// Whether commands are being read from a user at a prompt
+    pub interactive: bool,
+    /// Whether each job runs in its own process group and is handed the
+    /// terminal while in the foreground
+    pub job_control: bool,
+    /// The process group of the job currently being started
+    pub job_pgid: Option<Pid>,
+    pub traps: Traps,
+    /// How many conditions are being tested, such as `if` and `while`
+    /// guards, whose failure doesn't trigger the ERR trap
+    pub condition_depth: u32,
+    pub options: Options,
+    /// The status of the last command substitution run while expanding the
+    /// current command, which a command with no words takes as its own
+    pub substitution_status: Option<ExitStatus>,
+    /// The fds the shell holds open for the `<(cmd)` and `>(cmd)` of the
+    /// commands being run, with the subshells at their other ends
+    pub process_substitutions: Vec<(OwnedFd, Pid)>,
+    /// Whether the ERR trap has already been considered for the failure in
+    /// `$?`, so the commands around the one that failed don't run it again
+    pub err_handled: bool,
+}
+
+/// A pending change in control flow. Command lists stop running as soon as
+/// one is set, unwinding until the loop that handles it is reached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlFlow {
+    Break(u32),
+    Continue(u32),
+    Return(i32),
+    /// Ctrl-C killed a foreground job, which stops everything the shell is
+    /// running and goes back to the prompt
+    Interrupt,
+}
+
+impl Executor {
+    pub fn new() -> Self {
+        //the shell starts with the default IFS, unexported, whatever it inherits
+        let mut variables = Variables::from_env();
+        variables.unset("IFS");
+        variables.set("IFS", expand::DEFAULT_IFS);
+        Executor {
+            history: vec![],
+            last_status: ExitStatus::from_raw(0),
+            variables,
+            arg0: env::args_os().next().unwrap_or_else(|| OsString::from("arsh")),
+            positional: vec![],
+            pid: process::id(),
+            last_bg_pid: None,
+            control_flow: None,
+            loop_depth: 0,
+            functions: HashMap::new(),
+            function_depth: 0,
+            jobs: JobTable::default(),
+            interactive: false,
+            job_control: false,
+            job_pgid: None,
+            traps: Traps::default(),
+            condition_depth: 0,
+            options: Options::default(),
+            substitution_status: None,
+            process_substitutions: vec![],
+            err_handled: false,
+        }
+    }
+}
+
+impl Default for Executor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Builds the status of a process that exited normally with `code`
+pub fn exit_status(code: i32) -> ExitStatus {
+    ExitStatus::from_raw((code & 0xff) << 8)
+}
+
+/// The numeric form of a status as seen through `$?`, where death by a
+/// signal is reported as 128 plus the signal number
+pub fn status_code(status: &ExitStatus) -> i32 {
+    match status.code() {
+        Some(code) => code,
+        None       => 128 + status.signal().unwrap_or(0),
+    }
+}
+
+/// Waits for a child process to finish and returns how it ended
+pub fn wait_for(pid: Pid) -> Result<ExitStatus> {
+    loop {
+        match signals::wait(pid)? {
+            WaitStatus::Exited(_pid, code) => return Ok(exit_status(code)),
+            WaitStatus::Signaled(_pid, signal, _) => return Ok(ExitStatus::from_raw(signal as i32)),
+            _ => continue,
+        }
+    }
 }
 
-pub fn execute(mut commands: Vec<Box<dyn Execute>>) -> ExitStatus {
-    let mut status = ExitStatus::from_raw(0);
-    for command in &mut commands {
-        status = match command.execute() {
-            Ok(status) => status,
-            Err(msg)   => {
-                eprintln!("Execution error: {}", msg);
-                ExitStatus::from_raw(1)
+/// Runs `run` in a forked copy of the shell with the given standard input
+/// and output, returning the child's pid without waiting for it. The child
+/// exits with the status `run` returns.
+pub fn fork_subshell<F>(shell: &mut Executor, stdin: Option<OwnedFd>, stdout: Option<OwnedFd>, run: F) -> Result<Pid>
+where
+    F: FnOnce(&mut Executor) -> ExitStatus,
+{
+    match unsafe{ nix::unistd::fork()? } {
+        ForkResult::Parent{child} => {
+            join_job(shell, child);
+            Ok(child)
+        },
+        ForkResult::Child => {
+            if shell.job_control {
+                let pgid = shell.job_pgid.unwrap_or(Pid::from_raw(0));
+                let _ = nix::unistd::setpgid(Pid::from_raw(0), pgid);
+            }
+            //jobs belong to the parent shell, not to its subshells
+            shell.jobs = JobTable::default();
+            shell.interactive = false;
+            shell.job_control = false;
+            if let Some(stdin) = stdin {
+                let _ = nix::unistd::dup2(stdin.as_raw_fd(), 0);
+            }
+            if let Some(stdout) = stdout {
+                let _ = nix::unistd::dup2(stdout.as_raw_fd(), 1);
+            }
+            close_cloexec_fds();
+            signals::reset_subshell();
+            shell.traps.reset_for_subshell();
+            let status = run(shell);
+            exit_shell(shell, status_code(&status))
+        },
+    }
+}
+
+/// Runs commands in a subshell whose standard output goes to a pipe, and
+/// returns every byte written there. The pipe is read while the subshell
+/// runs, so it can't fill up and stall it. `$?` becomes the subshell's
+/// status.
+pub fn capture_output(shell: &mut Executor, commands: &mut [Box<dyn Execute>]) -> Result<Vec<u8>> {
+    let (mut reader, writer) = io::pipe()?;
+    //the substituted commands belong to the command being expanded rather
+    //than being jobs of their own
+    let job_control = mem::replace(&mut shell.job_control, false);
+    let child = fork_subshell(shell, None, Some(writer.into()), |shell| execute_list(commands, shell));
+    shell.job_control = job_control;
+    let child = child?;
+    let mut output = vec![];
+    let read = reader.read_to_end(&mut output);
+    let status = wait_for(child)?;
+    read?;
+    check_interrupt(shell, status);
+    shell.last_status = status;
+    shell.substitution_status = Some(status);
+    Ok(output)
+}
+
+/// Runs commands in a subshell connected to a pipe, whose other end the
+/// shell keeps open and returns a `/dev/fd` path for. With `readable` the
+/// path reads the commands' output, as for `<(cmd)`, and otherwise writing
+/// to it feeds their input, as for `>(cmd)`.
+pub fn process_substitution(shell: &mut Executor, commands: &mut [Box<dyn Execute>], readable: bool) -> Result<String> {
+    let (reader, writer) = io::pipe()?;
+    let (kept, stdin, stdout): (OwnedFd, _, _) = match readable {
+        true  => (reader.into(), None, Some(writer.into())),
+        false => (writer.into(), Some(reader.into()), None),
+    };
+    let job_control = mem::replace(&mut shell.job_control, false);
+    let child = fork_subshell(shell, stdin, stdout, |shell| execute_list(commands, shell));
+    shell.job_control = job_control;
+    let child = child?;
+    //the command the path is given to has to inherit the fd
+    let kept = move_fd_high(kept)?;
+    fcntl(kept.as_raw_fd(), FcntlArg::F_SETFD(FdFlag::empty()))?;
+    let path = format!("/dev/fd/{}", kept.as_raw_fd());
+    shell.process_substitutions.push((kept, child));
+    Ok(path)
+}
+
+/// Closes the fds of the process substitutions started since there were
+/// `mark` of them, then waits for their subshells to finish
+fn finish_process_substitutions(shell: &mut Executor, mark: usize) {
+    if shell.process_substitutions.len() <= mark {
+        return;
+    }
+    for (fd, child) in shell.process_substitutions.split_off(mark) {
+        drop(fd);
+        let _ = wait_for(child);
+    }
+}
+
+/// Spawns an external command as one of the processes of the job being
+/// started, without waiting for it
+pub fn spawn(shell: &mut Executor, command: &mut process::Command) -> Result<Pid> {
+    if shell.job_control {
+        command.process_group(shell.job_pgid.map_or(0, Pid::as_raw));
+    }
+    unsafe {
+        command.pre_exec(|| {
+            signals::reset();
+            Ok(())
+        });
+    }
+    let child = Pid::from_raw(command.spawn()?.id() as i32);
+    join_job(shell, child);
+    Ok(child)
+}
+
+/// Puts a new child in the process group of the job being started, the
+/// first child of a job becoming its group leader. Both parent and child
+/// set the group so neither can run ahead of it.
+fn join_job(shell: &mut Executor, child: Pid) {
+    if shell.job_control {
+        let pgid = *shell.job_pgid.get_or_insert(child);
+        let _ = nix::unistd::setpgid(child, pgid);
+    }
+}
+
+/// Waits for the processes of a foreground job. If the job is stopped it
+/// is added to the job table instead.
+pub fn wait_for_job(shell: &mut Executor, pids: Vec<Pid>, command: String) -> Result<ExitStatus> {
+    let mut job = Job::new(shell.job_pgid.take(), pids, command);
+    job.wait_in_foreground()?;
+    let status = job.status();
+    check_interrupt(shell, status);
+    if job.state() == JobState::Stopped {
+        let id = shell.jobs.add(job);
+        shell.jobs.report_stopped(id);
+    }
+    Ok(status)
+}
+
+/// Only the foreground job sees the SIGINT of a Ctrl-C, so when it dies of
+/// one the shell unwinds whatever list or loop ran it, as bash does
+fn check_interrupt(shell: &mut Executor, status: ExitStatus) {
+    if shell.interactive && status.signal() == Some(Signal::SIGINT as i32) {
+        shell.control_flow = Some(ControlFlow::Interrupt);
+    }
+}
+
+/// The lowest fd the shell uses for itself. Lower ones are left for
+/// scripts to redirect, as in `exec 3<file`.
+pub const SHELL_FD_BASE: RawFd = 10;
+
+/// Moves an fd the shell opened for its own use to `SHELL_FD_BASE` or
+/// above, closing the original. The new fd is close-on-exec.
+pub fn move_fd_high(fd: OwnedFd) -> nix::Result<OwnedFd> {
+    if fd.as_raw_fd() >= SHELL_FD_BASE {
+        return Ok(fd);
+    }
+    let high = fcntl(fd.as_raw_fd(), FcntlArg::F_DUPFD_CLOEXEC(SHELL_FD_BASE))?;
+    Ok(unsafe { OwnedFd::from_raw_fd(high) })
+}
+
+/// A forked subshell never calls exec, so descriptors the shell opened for
+/// its own use (all of which are close-on-exec) have to be closed by hand.
+/// Otherwise the subshell could hold the read end of its own output pipe
+/// open and never see a broken pipe.
+fn close_cloexec_fds() {
+    let fds: Vec<i32> = match std::fs::read_dir("/dev/fd") {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+            .collect(),
+        Err(_) => return,
+    };
+    for fd in fds {
+        if let Ok(flags) = fcntl(fd, FcntlArg::F_GETFD) {
+            if FdFlag::from_bits_truncate(flags).contains(FdFlag::FD_CLOEXEC) {
+                let _ = nix::unistd::close(fd);
             }
         }
     }
+}
+
+/// Ends the shell with `code`, which the EXIT trap sees as `$?`
+pub fn exit_shell(shell: &mut Executor, code: i32) -> ! {
+    shell.last_status = exit_status(code);
+    traps::run_exit(shell);
+    //process::exit doesn't flush what builtins left buffered
+    let _ = io::stdout().flush();
+    process::exit(code)
+}
+
+pub fn execute(mut commands: Vec<Box<dyn Execute>>, shell: &mut Executor) -> ExitStatus {
+    let status = execute_list(&mut commands, shell);
+    //an interrupt unwinds as far as the prompt and no further
+    if shell.control_flow == Some(ControlFlow::Interrupt) {
+        shell.control_flow = None;
+    }
+    status
+}
+
+/// Runs a single command, reporting any error and treating it as failure.
+/// The result becomes the new value of `$?`.
+pub fn execute_or_report(command: &mut dyn Execute, shell: &mut Executor) -> ExitStatus {
+    let mark = shell.process_substitutions.len();
+    shell.err_handled = false;
+    let status = match command.execute(shell) {
+        Ok(status) => status,
+        Err(msg)   => {
+            eprintln!("Execution error: {}", msg);
+            exit_status(1)
+        }
+    };
+    //process substitutions last as long as the command they were made for
+    finish_process_substitutions(shell, mark);
+    shell.last_status = status;
+    //a brace group or loop fails with the status of the command in it that
+    //failed, which has had its turn at the trap already. Commands run in a
+    //function don't count, so the call itself is what fails.
+    if !status.success() && !shell.err_handled && shell.function_depth == 0 {
+        traps::run_err(shell);
+        shell.err_handled = true;
+    }
+    shell.last_status
+}
+
+/// Runs commands in sequence, stopping early if a `break` or `continue`
+/// is pending. Returns the status of the last command that ran.
+pub fn execute_list(commands: &mut [Box<dyn Execute>], shell: &mut Executor) -> ExitStatus {
+    let mut status = exit_status(0);
+    for command in commands {
+        status = execute_or_report(command.as_mut(), shell);
+        traps::run_pending(shell);
+        if shell.control_flow.is_some() {
+            break;
+        }
+    }
+    status
+}
+
+/// Runs commands whose status is being tested rather than reported, as in
+/// an `if` or `while` guard
+pub fn execute_condition(commands: &mut [Box<dyn Execute>], shell: &mut Executor) -> ExitStatus {
+    shell.condition_depth += 1;
+    let status = execute_list(commands, shell);
+    shell.condition_depth -= 1;
     status
 }
@@ -0,0 +1,134 @@
+use anyhow::{anyhow, Result};
+
+/// Options turned on and off with `set -o name` and `set +o name`, or
+/// with their single letter flags, and with `shopt -s name` and `shopt -u
+/// name`
+#[derive(Debug)]
+pub struct Options {
+    /// `-B`: `{a,b}` and `{1..3}` in words are expanded
+    pub braceexpand: bool,
+    /// `-C`: `>` refuses to overwrite an existing file, though `>|` still can
+    pub noclobber: bool,
+    /// A pattern that matches nothing is removed rather than kept as it is
+    pub nullglob: bool,
+    /// A pattern that matches nothing is an error
+    pub failglob: bool,
+    /// Patterns match names starting with `.` without one being written
+    pub dotglob: bool,
+    /// `**` in a pattern matches any number of directories
+    pub globstar: bool,
+}
+
+/// Each option `set` knows, with its flag letter
+const NAMES: [(&str, Option<char>); 2] = [
+    ("braceexpand", Some('B')),
+    ("noclobber", Some('C')),
+];
+
+/// The options `shopt` sets
+const SHOPT_NAMES: [&str; 4] = ["dotglob", "failglob", "globstar", "nullglob"];
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            braceexpand: true,
+            noclobber: false,
+            nullglob: false,
+            failglob: false,
+            dotglob: false,
+            globstar: false,
+        }
+    }
+}
+
+impl Options {
+    fn flag_mut(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "braceexpand" => Some(&mut self.braceexpand),
+            "noclobber" => Some(&mut self.noclobber),
+            "nullglob"  => Some(&mut self.nullglob),
+            "failglob"  => Some(&mut self.failglob),
+            "dotglob"   => Some(&mut self.dotglob),
+            "globstar"  => Some(&mut self.globstar),
+            _           => None,
+        }
+    }
+
+    fn flag(&self, name: &str) -> bool {
+        match name {
+            "braceexpand" => self.braceexpand,
+            "noclobber" => self.noclobber,
+            "nullglob"  => self.nullglob,
+            "failglob"  => self.failglob,
+            "dotglob"   => self.dotglob,
+            "globstar"  => self.globstar,
+            _           => false,
+        }
+    }
+
+    pub fn set(&mut self, name: &str, on: bool) -> Result<()> {
+        if !NAMES.iter().any(|(known, _)| *known == name) {
+            return Err(anyhow!("set: {}: invalid option name", name));
+        }
+        self.set_flag(name, on);
+        Ok(())
+    }
+
+    pub fn shopt(&mut self, name: &str, on: bool) -> Result<()> {
+        if !SHOPT_NAMES.contains(&name) {
+            return Err(anyhow!("shopt: {}: invalid shell option name", name));
+        }
+        self.set_flag(name, on);
+        Ok(())
+    }
+
+    fn set_flag(&mut self, name: &str, on: bool) {
+        if let Some(flag) = self.flag_mut(name) {
+            *flag = on;
+        }
+    }
+
+    pub fn set_letter(&mut self, letter: char, on: bool) -> Result<()> {
+        let (name, _) = NAMES.iter()
+            .find(|(_, flag)| *flag == Some(letter))
+            .ok_or_else(|| anyhow!("set: -{}: invalid option", letter))?;
+        self.set(name, on)
+    }
+
+    /// Lists every option as `set -o` does
+    pub fn print(&self) {
+        for (name, _) in NAMES {
+            println!("{:<15}\t{}", name, if self.flag(name) { "on" } else { "off" });
+        }
+    }
+
+    /// Lists `shopt` options, or only those named, either with whether
+    /// they're on or as commands that would set them again
+    pub fn print_shopt(&self, names: &[String], as_commands: bool) -> Result<()> {
+        let names: Vec<&str> = match names.is_empty() {
+            true  => SHOPT_NAMES.to_vec(),
+            false => names.iter().map(String::as_str).collect(),
+        };
+        for name in names {
+            if !SHOPT_NAMES.contains(&name) {
+                return Err(anyhow!("shopt: {}: invalid shell option name", name));
+            }
+            let on = self.flag(name);
+            if as_commands {
+                println!("shopt {} {}", if on { "-s" } else { "-u" }, name);
+            }
+            else {
+                println!("{:<15}\t{}", name, if on { "on" } else { "off" });
+            }
+        }
+        Ok(())
+    }
+
+    /// Lists every option as commands that would set them again, as
+    /// `set +o` does
+    pub fn print_commands(&self) {
+        for (name, _) in NAMES {
+            println!("set {}o {}", if self.flag(name) { '-' } else { '+' }, name);
+        }
+    }
+}
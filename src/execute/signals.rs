@@ -0,0 +1,241 @@
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::os::fd::{FromRawFd, IntoRawFd, OwnedFd};
+use super::move_fd_high;
+use nix::unistd::{self, Pid};
+use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+use nix::sys::signal::{self, SaFlags, SigAction, SigHandler, SigSet, Signal};
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::fcntl::OFlag;
+use nix::errno::Errno;
+use anyhow::Result;
+
+//the self-pipe: the SIGCHLD handler writes a byte to wake up a waiting shell
+static CHILD_PIPE_READ: AtomicI32 = AtomicI32::new(-1);
+static CHILD_PIPE_WRITE: AtomicI32 = AtomicI32::new(-1);
+/// Set by SIGINT in an interactive shell, which otherwise ignores it
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+/// Whether the shell changed the dispositions children need reset
+static DISPOSITIONS_CHANGED: AtomicBool = AtomicBool::new(false);
+/// Trapped signals that have arrived but whose traps haven't run, one bit
+/// per signal number
+static PENDING: AtomicU64 = AtomicU64::new(0);
+/// Signals ignored with `trap ''`, which commands inherit
+static IGNORED: AtomicU64 = AtomicU64::new(0);
+/// Status changes that have been reaped but not yet asked for
+static STATUSES: Mutex<Vec<WaitStatus>> = Mutex::new(Vec::new());
+
+/// The signals an interactive shell keeps from affecting itself
+const SHELL_SIGNALS: [Signal; 5] = [Signal::SIGINT, Signal::SIGQUIT, Signal::SIGTSTP, Signal::SIGTTIN, Signal::SIGTTOU];
+
+extern "C" fn on_sigchld(_signal: i32) {
+    let fd = CHILD_PIPE_WRITE.load(Ordering::Relaxed);
+    if fd >= 0 {
+        let _ = unistd::write(fd, &[0]);
+    }
+}
+
+extern "C" fn on_sigint(_signal: i32) {
+    INTERRUPTED.store(true, Ordering::Relaxed);
+}
+
+extern "C" fn on_trapped(signal: i32) {
+    PENDING.fetch_or(1 << signal, Ordering::Relaxed);
+    //a trap replaces the shell's own handler, which still has to run
+    if signal == Signal::SIGINT as i32 {
+        on_sigint(signal);
+    }
+    else if signal == Signal::SIGCHLD as i32 {
+        on_sigchld(signal);
+    }
+}
+
+/// Installs the SIGCHLD handler that children are reaped through
+pub fn init() -> Result<()> {
+    open_child_pipe()?;
+    let action = SigAction::new(SigHandler::Handler(on_sigchld), SaFlags::SA_RESTART, SigSet::empty());
+    unsafe {
+        signal::sigaction(Signal::SIGCHLD, &action)?;
+    }
+    Ok(())
+}
+
+/// Keeps keyboard signals from killing or stopping an interactive shell.
+/// SIGINT is caught rather than ignored, and isn't restarted, so that a
+/// read at the prompt is interrupted by it.
+pub fn init_interactive() -> Result<()> {
+    let interrupt = SigAction::new(SigHandler::Handler(on_sigint), SaFlags::empty(), SigSet::empty());
+    let ignore = SigAction::new(SigHandler::SigIgn, SaFlags::empty(), SigSet::empty());
+    unsafe {
+        signal::sigaction(Signal::SIGINT, &interrupt)?;
+        for signal in &SHELL_SIGNALS[1..] {
+            signal::sigaction(*signal, &ignore)?;
+        }
+    }
+    DISPOSITIONS_CHANGED.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Restores default dispositions in a child that is about to run a
+/// command, so it can be interrupted and stopped from the terminal
+pub fn reset() {
+    if !DISPOSITIONS_CHANGED.load(Ordering::Relaxed) {
+        return;
+    }
+    let ignored = IGNORED.load(Ordering::Relaxed);
+    for signal in SHELL_SIGNALS {
+        if ignored & (1 << signal as i32) == 0 {
+            unsafe {
+                let _ = signal::signal(signal, SigHandler::SigDfl);
+            }
+        }
+    }
+}
+
+/// Catches a signal so that its trap runs at the next safe point
+pub fn catch(signal: Signal) -> Result<()> {
+    IGNORED.fetch_and(!(1 << signal as i32), Ordering::Relaxed);
+    let action = SigAction::new(SigHandler::Handler(on_trapped), SaFlags::empty(), SigSet::empty());
+    unsafe {
+        signal::sigaction(signal, &action)?;
+    }
+    Ok(())
+}
+
+pub fn ignore(signal: Signal) -> Result<()> {
+    if signal == Signal::SIGCHLD {
+        return catch(signal);
+    }
+    IGNORED.fetch_or(1 << signal as i32, Ordering::Relaxed);
+    unsafe {
+        signal::signal(signal, SigHandler::SigIgn)?;
+    }
+    Ok(())
+}
+
+/// Gives a signal back the disposition the shell normally has for it
+pub fn restore(signal: Signal) -> Result<()> {
+    IGNORED.fetch_and(!(1 << signal as i32), Ordering::Relaxed);
+    PENDING.fetch_and(!(1 << signal as i32), Ordering::Relaxed);
+    let handler = match signal {
+        Signal::SIGCHLD => SigHandler::Handler(on_sigchld),
+        Signal::SIGINT if DISPOSITIONS_CHANGED.load(Ordering::Relaxed) => SigHandler::Handler(on_sigint),
+        _ if DISPOSITIONS_CHANGED.load(Ordering::Relaxed) && SHELL_SIGNALS.contains(&signal) => SigHandler::SigIgn,
+        _ => SigHandler::SigDfl,
+    };
+    let flags = match signal {
+        Signal::SIGCHLD => SaFlags::SA_RESTART,
+        _               => SaFlags::empty(),
+    };
+    unsafe {
+        signal::sigaction(signal, &SigAction::new(handler, flags, SigSet::empty()))?;
+    }
+    Ok(())
+}
+
+/// The trapped signals that arrived since the last call
+pub fn take_pending() -> Vec<Signal> {
+    let pending = PENDING.swap(0, Ordering::Relaxed);
+    Signal::iterator()
+        .filter(|signal| pending & (1 << *signal as i32) != 0)
+        .collect()
+}
+
+/// Gives a forked subshell a self-pipe and status list of its own, so it
+/// doesn't steal wakeups meant for its parent
+pub fn reset_subshell() {
+    reset();
+    DISPOSITIONS_CHANGED.store(false, Ordering::Relaxed);
+    PENDING.store(0, Ordering::Relaxed);
+    STATUSES.lock().unwrap_or_else(|e| e.into_inner()).clear();
+    for fd in [&CHILD_PIPE_READ, &CHILD_PIPE_WRITE] {
+        let fd = fd.swap(-1, Ordering::Relaxed);
+        if fd >= 0 {
+            let _ = unistd::close(fd);
+        }
+    }
+    let _ = open_child_pipe();
+}
+
+/// Whether SIGINT arrived since the last call
+pub fn take_interrupt() -> bool {
+    INTERRUPTED.swap(false, Ordering::Relaxed)
+}
+
+fn open_child_pipe() -> Result<()> {
+    let (read, write) = unistd::pipe2(OFlag::O_CLOEXEC | OFlag::O_NONBLOCK)?;
+    //keep out of the way of fds scripts redirect
+    let read = move_fd_high(unsafe { OwnedFd::from_raw_fd(read) })?;
+    let write = move_fd_high(unsafe { OwnedFd::from_raw_fd(write) })?;
+    CHILD_PIPE_READ.store(read.into_raw_fd(), Ordering::Relaxed);
+    CHILD_PIPE_WRITE.store(write.into_raw_fd(), Ordering::Relaxed);
+    Ok(())
+}
+
+/// Reaps every child that has changed state, keeping its status until asked for
+fn collect() {
+    let mut statuses = STATUSES.lock().unwrap_or_else(|e| e.into_inner());
+    let flags = WaitPidFlag::WNOHANG | WaitPidFlag::WUNTRACED | WaitPidFlag::WCONTINUED;
+    loop {
+        match waitpid(None, Some(flags)) {
+            Ok(WaitStatus::StillAlive) | Err(_) => break,
+            Ok(status) => statuses.push(status),
+        }
+    }
+}
+
+fn take_status(pid: Pid) -> Option<WaitStatus> {
+    let mut statuses = STATUSES.lock().unwrap_or_else(|e| e.into_inner());
+    let index = statuses.iter().position(|status| status.pid() == Some(pid))?;
+    Some(statuses.remove(index))
+}
+
+/// The next state change of a child, if there has been one, without blocking
+pub fn try_wait(pid: Pid) -> Option<WaitStatus> {
+    collect();
+    take_status(pid)
+}
+
+/// Blocks until a child changes state. Fails with ECHILD if `pid` isn't a
+/// child of the shell.
+pub fn wait(pid: Pid) -> Result<WaitStatus, Errno> {
+    let pipe = CHILD_PIPE_READ.load(Ordering::Relaxed);
+    loop {
+        //drain before collecting, so a SIGCHLD arriving after the collect
+        //still leaves a byte behind to wake the poll
+        let mut buf = [0; 64];
+        while pipe >= 0 && matches!(unistd::read(pipe, &mut buf), Ok(n) if n > 0) {}
+        if let Some(status) = try_wait(pid) {
+            return Ok(status);
+        }
+        let flags = WaitPidFlag::WNOHANG | WaitPidFlag::WUNTRACED | WaitPidFlag::WCONTINUED;
+        match waitpid(pid, Some(flags)) {
+            Ok(WaitStatus::StillAlive) => {},
+            Ok(status) => return Ok(status),
+            Err(Errno::EINTR) => continue,
+            Err(e) => return Err(e),
+        }
+        if pipe < 0 {
+            //no handler installed, so just block on this child
+            return loop {
+                match waitpid(pid, Some(WaitPidFlag::WUNTRACED | WaitPidFlag::WCONTINUED)) {
+                    Err(Errno::EINTR) => continue,
+                    result => break result,
+                }
+            };
+        }
+        let mut fds = [PollFd::new(pipe, PollFlags::POLLIN)];
+        match poll(&mut fds, -1) {
+            Ok(_) | Err(Errno::EINTR) => {},
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Forgets collected statuses of children that aren't in `pids`, so a
+/// reused pid can't pick up a stale status
+pub fn discard_except(pids: &[Pid]) {
+    collect();
+    let mut statuses = STATUSES.lock().unwrap_or_else(|e| e.into_inner());
+    statuses.retain(|status| status.pid().is_some_and(|pid| pids.contains(&pid)));
+}
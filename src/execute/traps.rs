@@ -0,0 +1,173 @@
+use crate::execute::{Executor, execute_list, signals};
+use crate::command::parse_into_commands;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use nix::sys::signal::Signal;
+use anyhow::{anyhow, Result};
+
+/// A condition a trap can be set on: a signal, or one of the shell's own
+/// pseudo-signals
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Trap {
+    /// The shell is exiting
+    Exit,
+    /// A command failed outside of a condition
+    Err,
+    /// A simple command is about to run
+    Debug,
+    Signal(Signal),
+}
+
+/// What the shell does when a trap's condition occurs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    /// Run a command string
+    Command(String),
+    /// Set with `trap '' SIG`, which ignores the signal
+    Ignore,
+}
+
+/// The traps set with the `trap` builtin
+#[derive(Debug, Default)]
+pub struct Traps {
+    actions: BTreeMap<Trap, Action>,
+    /// Whether a trap action is running, during which no other traps fire
+    running: bool,
+}
+
+impl FromStr for Trap {
+    type Err = anyhow::Error;
+
+    /// Accepts names with or without `SIG`, in any case, or signal numbers
+    fn from_str(spec: &str) -> Result<Trap> {
+        let name = spec.to_uppercase();
+        let trap = match name.as_str() {
+            "EXIT" | "0" => Trap::Exit,
+            "ERR"        => Trap::Err,
+            "DEBUG"      => Trap::Debug,
+            _ => {
+                let signal = match name.parse::<i32>() {
+                    Ok(number) => Signal::try_from(number).ok(),
+                    Err(_) if name.starts_with("SIG") => name.parse().ok(),
+                    Err(_) => format!("SIG{}", name).parse().ok(),
+                };
+                Trap::Signal(signal.ok_or_else(|| anyhow!("{}: invalid signal specification", spec))?)
+            },
+        };
+        Ok(trap)
+    }
+}
+
+impl fmt::Display for Trap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Trap::Exit   => write!(f, "EXIT"),
+            Trap::Err    => write!(f, "ERR"),
+            Trap::Debug  => write!(f, "DEBUG"),
+            Trap::Signal(signal) => write!(f, "{}", signal.as_str()),
+        }
+    }
+}
+
+impl Traps {
+    pub fn set(&mut self, trap: Trap, action: Action) -> Result<()> {
+        if let Trap::Signal(signal) = trap {
+            match action {
+                Action::Ignore     => signals::ignore(signal)?,
+                Action::Command(_) => signals::catch(signal)?,
+            }
+        }
+        self.actions.insert(trap, action);
+        Ok(())
+    }
+
+    /// Puts a trap back to what the shell does by default
+    pub fn reset(&mut self, trap: Trap) -> Result<()> {
+        if let Trap::Signal(signal) = trap {
+            signals::restore(signal)?;
+        }
+        self.actions.remove(&trap);
+        Ok(())
+    }
+
+    /// Lists traps in a form that can be read back in to set them again
+    pub fn print(&self, traps: &[Trap]) {
+        for (trap, action) in &self.actions {
+            if !traps.is_empty() && !traps.contains(trap) {
+                continue;
+            }
+            let command = match action {
+                Action::Command(command) => command.replace('\'', "'\\''"),
+                Action::Ignore           => String::new(),
+            };
+            println!("trap -- '{}' {}", command, trap);
+        }
+    }
+
+    /// A subshell starts without the parent's traps, except that ignored
+    /// signals stay ignored
+    pub fn reset_for_subshell(&mut self) {
+        let caught: Vec<Trap> = self.actions.iter()
+            .filter(|(_, action)| matches!(action, Action::Command(_)))
+            .map(|(trap, _)| *trap)
+            .collect();
+        for trap in caught {
+            let _ = self.reset(trap);
+        }
+    }
+
+    fn command(&self, trap: Trap) -> Option<String> {
+        match self.actions.get(&trap)? {
+            Action::Command(command) => Some(command.clone()),
+            Action::Ignore           => None,
+        }
+    }
+}
+
+/// Runs a trap's command string, leaving `$?` as it was before
+fn run(shell: &mut Executor, trap: Trap) {
+    let Some(command) = shell.traps.command(trap) else { return };
+    if shell.traps.running {
+        return;
+    }
+    shell.traps.running = true;
+    let (last_status, err_handled) = (shell.last_status, shell.err_handled);
+    match parse_into_commands(&command) {
+        Ok(mut commands) => {
+            execute_list(&mut commands, shell);
+        },
+        Err(e) => eprintln!("trap: error parsing {}: {}", trap, e),
+    }
+    shell.last_status = last_status;
+    shell.err_handled = err_handled;
+    shell.traps.running = false;
+}
+
+/// Runs the traps of any signals that arrived since the last safe point
+pub fn run_pending(shell: &mut Executor) {
+    for signal in signals::take_pending() {
+        run(shell, Trap::Signal(signal));
+    }
+}
+
+/// Runs the EXIT trap, at most once
+pub fn run_exit(shell: &mut Executor) {
+    run(shell, Trap::Exit);
+    shell.traps.actions.remove(&Trap::Exit);
+}
+
+/// Runs the ERR trap after a command fails. Functions don't inherit it,
+/// and failures in conditions don't count.
+pub fn run_err(shell: &mut Executor) {
+    if shell.condition_depth == 0 && shell.function_depth == 0 {
+        run(shell, Trap::Err);
+    }
+}
+
+/// Runs the DEBUG trap before a simple command. Functions don't inherit it.
+pub fn run_debug(shell: &mut Executor) {
+    if shell.function_depth == 0 {
+        run(shell, Trap::Debug);
+    }
+}
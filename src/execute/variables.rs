@@ -0,0 +1,122 @@
+use std::collections::HashMap;
+use std::env;
+use std::ffi::{OsStr, OsString};
+
+#[derive(Debug, Clone)]
+pub struct Variable {
+    pub value: OsString,
+    pub exported: bool,
+}
+
+/// The shell's variable store. Exported variables are mirrored into the
+/// process environment so that spawned commands inherit them.
+#[derive(Debug, Default)]
+pub struct Variables {
+    vars: HashMap<String, Variable>,
+    //one frame per running function, holding the values its locals shadow
+    scopes: Vec<Vec<(String, Option<Variable>)>>,
+}
+
+impl Variables {
+    pub fn from_env() -> Self {
+        let vars = env::vars_os()
+            .map(|(name, value)| (name.to_string_lossy().into_owned(), Variable { value, exported: true }))
+            .collect();
+        Variables { vars, scopes: vec![] }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&OsStr> {
+        self.vars.get(name).map(|var| var.value.as_os_str())
+    }
+
+    pub fn set(&mut self, name: &str, value: impl Into<OsString>) {
+        let value = value.into();
+        match self.vars.get_mut(name) {
+            Some(var) => {
+                if var.exported {
+                    env::set_var(name, &value);
+                }
+                var.value = value;
+            },
+            None => {
+                let exported = self.shadows_export(name);
+                if exported {
+                    env::set_var(name, &value);
+                }
+                self.vars.insert(name.to_string(), Variable { value, exported });
+            },
+        }
+    }
+
+    /// Marks a variable for export, creating it empty if it doesn't exist
+    pub fn export(&mut self, name: &str) {
+        let var = self.vars
+            .entry(name.to_string())
+            .or_insert(Variable { value: OsString::new(), exported: false });
+        var.exported = true;
+        env::set_var(name, &var.value);
+    }
+
+    pub fn unset(&mut self, name: &str) {
+        if let Some(var) = self.vars.remove(name) {
+            if var.exported {
+                env::remove_var(name);
+            }
+        }
+    }
+
+    /// A copy of a variable with its attributes, to be put back later
+    /// with `restore`
+    pub fn save(&self, name: &str) -> Option<Variable> {
+        self.vars.get(name).cloned()
+    }
+
+    /// Puts a variable back as it was saved, or unsets it if it wasn't set
+    pub fn restore(&mut self, name: &str, saved: Option<Variable>) {
+        self.unset(name);
+        if let Some(var) = saved {
+            if var.exported {
+                env::set_var(name, &var.value);
+            }
+            self.vars.insert(name.to_string(), var);
+        }
+    }
+
+    /// Iterates over all variables in name order
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Variable)> {
+        let mut vars: Vec<_> = self.vars.iter().collect();
+        vars.sort_by(|a, b| a.0.cmp(b.0));
+        vars.into_iter()
+    }
+
+    pub fn push_scope(&mut self) {
+        self.scopes.push(vec![]);
+    }
+
+    /// Restores every variable shadowed by a `local` in the innermost scope
+    pub fn pop_scope(&mut self) {
+        let Some(scope) = self.scopes.pop() else { return };
+        for (name, saved) in scope.into_iter().rev() {
+            self.restore(&name, saved);
+        }
+    }
+
+    /// Saves the current value of `name` in the innermost scope and unsets
+    /// it, so the caller's value comes back when the scope is popped. A
+    /// local that shadows an exported variable is exported once assigned.
+    pub fn make_local(&mut self, name: &str) {
+        let Some(scope) = self.scopes.last_mut() else { return };
+        if scope.iter().any(|(local, _)| local == name) {
+            return;
+        }
+        scope.push((name.to_string(), self.vars.get(name).cloned()));
+        self.unset(name);
+    }
+
+    /// Whether the innermost `local` of `name` shadows an exported variable
+    fn shadows_export(&self, name: &str) -> bool {
+        self.scopes.iter().rev()
+            .find_map(|scope| scope.iter().find(|(local, _)| local == name))
+            .is_some_and(|(_, saved)| saved.as_ref().is_some_and(|var| var.exported))
+    }
+}
@@ -0,0 +1,127 @@
+use crate::execute::Executor;
+use conch_parser::ast::Arithmetic;
+use conch_parser::lexer::Lexer;
+use conch_parser::parse::DefaultParser;
+use anyhow::{anyhow, Result};
+use std::ffi::OsStr;
+
+/// How deeply variables whose values are themselves expressions can refer
+/// to one another before evaluation gives up
+const MAX_DEPTH: usize = 1024;
+
+/// Parses the text of an arithmetic expression, as given to `let`
+pub fn parse(text: &str) -> Result<Arithmetic<String>> {
+    let mut parser = DefaultParser::new(Lexer::new(text.chars()));
+    let expr = parser.arithmetic_substitution()
+        .map_err(|e| anyhow!("{}: syntax error in expression ({})", text, e))?;
+    parser.skip_whitespace();
+    if parser.pos().byte != text.len() {
+        return Err(anyhow!("{}: syntax error in expression", text));
+    }
+    Ok(expr)
+}
+
+/// Evaluates an arithmetic expression with 64-bit signed integers, which
+/// wrap on overflow. Assignments and increments change shell variables.
+pub fn evaluate(shell: &mut Executor, expr: &Arithmetic<String>) -> Result<i64> {
+    eval(shell, expr, 0)
+}
+
+fn eval(shell: &mut Executor, expr: &Arithmetic<String>, depth: usize) -> Result<i64> {
+    use Arithmetic::*;
+    let value = match expr {
+        Var(name)      => variable(shell, name, depth)?,
+        Literal(n)     => *n as i64,
+        PostIncr(name) => step(shell, name, 1, depth)?.wrapping_sub(1),
+        PostDecr(name) => step(shell, name, -1, depth)?.wrapping_add(1),
+        PreIncr(name)  => step(shell, name, 1, depth)?,
+        PreDecr(name)  => step(shell, name, -1, depth)?,
+        UnaryPlus(e)   => eval(shell, e, depth)?,
+        UnaryMinus(e)  => eval(shell, e, depth)?.wrapping_neg(),
+        LogicalNot(e)  => (eval(shell, e, depth)? == 0) as i64,
+        BitwiseNot(e)  => !eval(shell, e, depth)?,
+        Pow(base, exponent) => {
+            let base = eval(shell, base, depth)?;
+            let exponent = eval(shell, exponent, depth)?;
+            if exponent < 0 {
+                return Err(anyhow!("exponent less than 0"));
+            }
+            base.wrapping_pow(exponent.min(u32::MAX as i64) as u32)
+        },
+        Mult(l, r) => eval(shell, l, depth)?.wrapping_mul(eval(shell, r, depth)?),
+        Div(l, r) => {
+            let (l, r) = (eval(shell, l, depth)?, eval(shell, r, depth)?);
+            if r == 0 {
+                return Err(anyhow!("division by 0"));
+            }
+            l.wrapping_div(r)
+        },
+        Modulo(l, r) => {
+            let (l, r) = (eval(shell, l, depth)?, eval(shell, r, depth)?);
+            if r == 0 {
+                return Err(anyhow!("division by 0"));
+            }
+            l.wrapping_rem(r)
+        },
+        Add(l, r)        => eval(shell, l, depth)?.wrapping_add(eval(shell, r, depth)?),
+        Sub(l, r)        => eval(shell, l, depth)?.wrapping_sub(eval(shell, r, depth)?),
+        ShiftLeft(l, r)  => eval(shell, l, depth)?.wrapping_shl(eval(shell, r, depth)? as u32),
+        ShiftRight(l, r) => eval(shell, l, depth)?.wrapping_shr(eval(shell, r, depth)? as u32),
+        Less(l, r)       => (eval(shell, l, depth)? < eval(shell, r, depth)?) as i64,
+        LessEq(l, r)     => (eval(shell, l, depth)? <= eval(shell, r, depth)?) as i64,
+        Great(l, r)      => (eval(shell, l, depth)? > eval(shell, r, depth)?) as i64,
+        GreatEq(l, r)    => (eval(shell, l, depth)? >= eval(shell, r, depth)?) as i64,
+        Eq(l, r)         => (eval(shell, l, depth)? == eval(shell, r, depth)?) as i64,
+        NotEq(l, r)      => (eval(shell, l, depth)? != eval(shell, r, depth)?) as i64,
+        BitwiseAnd(l, r) => eval(shell, l, depth)? & eval(shell, r, depth)?,
+        BitwiseXor(l, r) => eval(shell, l, depth)? ^ eval(shell, r, depth)?,
+        BitwiseOr(l, r)  => eval(shell, l, depth)? | eval(shell, r, depth)?,
+        //the right side is only evaluated when it decides the result
+        LogicalAnd(l, r) => (eval(shell, l, depth)? != 0 && eval(shell, r, depth)? != 0) as i64,
+        LogicalOr(l, r)  => (eval(shell, l, depth)? != 0 || eval(shell, r, depth)? != 0) as i64,
+        Ternary(condition, then, otherwise) => match eval(shell, condition, depth)? {
+            0 => eval(shell, otherwise, depth)?,
+            _ => eval(shell, then, depth)?,
+        },
+        Assign(name, e) => {
+            let value = eval(shell, e, depth)?;
+            shell.variables.set(name, value.to_string());
+            value
+        },
+        Sequence(exprs) => {
+            let mut value = 0;
+            for e in exprs {
+                value = eval(shell, e, depth)?;
+            }
+            value
+        },
+    };
+    Ok(value)
+}
+
+/// The value of a variable in an expression. Unset and empty variables are
+/// 0, and any other value is evaluated as an expression itself.
+fn variable(shell: &mut Executor, name: &str, depth: usize) -> Result<i64> {
+    let text = shell.variables.get(name).map(OsStr::to_string_lossy).unwrap_or_default().trim().to_string();
+    if text.is_empty() {
+        return Ok(0);
+    }
+    //a leading 0 makes the number octal, which conch-parser knows about
+    if !text.trim_start_matches('-').starts_with('0') {
+        if let Ok(value) = text.parse::<i64>() {
+            return Ok(value);
+        }
+    }
+    if depth >= MAX_DEPTH {
+        return Err(anyhow!("{}: expression recursion level exceeded", name));
+    }
+    let expr = parse(&text)?;
+    eval(shell, &expr, depth + 1)
+}
+
+/// Adds `by` to a variable, returning its new value
+fn step(shell: &mut Executor, name: &str, by: i64, depth: usize) -> Result<i64> {
+    let value = variable(shell, name, depth)?.wrapping_add(by);
+    shell.variables.set(name, value.to_string());
+    Ok(value)
+}
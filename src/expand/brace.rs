@@ -0,0 +1,172 @@
+use crate::command::{ComplexWordAlias, WordAlias};
+use conch_parser::ast;
+
+/// A word broken down for brace expansion. Only unquoted literal text can
+/// hold the braces, commas and `..` of a brace expression, so everything
+/// else is kept whole.
+#[derive(Clone, PartialEq)]
+enum Piece<'a> {
+    Char(char),
+    Part(&'a WordAlias),
+}
+
+/// Expands `{a,b,c}` alternatives and `{x..y[..step]}` sequences in a word
+/// into several words, or returns `None` if it has no braces at all.
+/// Braces are expanded before anything else, so the words that result
+/// still have their quoting and substitutions to be expanded.
+pub fn expand(word: &ComplexWordAlias) -> Option<Vec<ComplexWordAlias>> {
+    let parts = match word {
+        ast::ComplexWord::Concat(parts) => parts.as_slice(),
+        ast::ComplexWord::Single(part) => std::slice::from_ref(part),
+    };
+    let mut pieces = vec![];
+    for part in parts {
+        match part {
+            ast::Word::Simple(ast::SimpleWord::Literal(text)) => pieces.extend(text.chars().map(Piece::Char)),
+            part => pieces.push(Piece::Part(part)),
+        }
+    }
+    if !pieces.contains(&Piece::Char('{')) {
+        return None;
+    }
+    Some(expand_from(pieces, 0).into_iter().map(into_word).collect())
+}
+
+/// Expands the first brace expression at or after `start`, then whatever
+/// follows in each of the words it makes
+fn expand_from(pieces: Vec<Piece>, start: usize) -> Vec<Vec<Piece>> {
+    let mut open = start;
+    loop {
+        let Some(found) = pieces[open..].iter().position(|piece| *piece == Piece::Char('{')) else {
+            return vec![pieces];
+        };
+        open += found;
+        //anything that isn't a brace expression is left as it is
+        let Some((close, commas)) = matching_brace(&pieces, open) else {
+            open += 1;
+            continue;
+        };
+        let alternatives: Vec<Vec<Piece>> = if commas.is_empty() {
+            match sequence(&pieces[open + 1..close]) {
+                Some(items) => items.iter().map(|item| item.chars().map(Piece::Char).collect()).collect(),
+                None => {
+                    open += 1;
+                    continue;
+                },
+            }
+        }
+        else {
+            let mut bounds = vec![open];
+            bounds.extend(commas);
+            bounds.push(close);
+            bounds.windows(2).map(|pair| pieces[pair[0] + 1..pair[1]].to_vec()).collect()
+        };
+        let mut words = vec![];
+        for alternative in alternatives {
+            let mut word = pieces[..open].to_vec();
+            word.extend(alternative);
+            word.extend_from_slice(&pieces[close + 1..]);
+            words.extend(expand_from(word, open));
+        }
+        return words;
+    }
+}
+
+/// Finds the `}` that closes the `{` at `open`, with the commas that
+/// separate its alternatives
+fn matching_brace(pieces: &[Piece], open: usize) -> Option<(usize, Vec<usize>)> {
+    let mut depth = 0;
+    let mut commas = vec![];
+    for (i, piece) in pieces.iter().enumerate().skip(open + 1) {
+        match piece {
+            Piece::Char('{') => depth += 1,
+            Piece::Char('}') if depth == 0 => return Some((i, commas)),
+            Piece::Char('}') => depth -= 1,
+            Piece::Char(',') if depth == 0 => commas.push(i),
+            _ => {},
+        }
+    }
+    None
+}
+
+/// The items of a sequence expression: `1..10`, `01..20..2` or `a..z`.
+/// Numbers are padded with zeros when either end is.
+fn sequence(pieces: &[Piece]) -> Option<Vec<String>> {
+    let text = pieces.iter()
+        .map(|piece| match piece {
+            Piece::Char(c) => Some(*c),
+            Piece::Part(_) => None,
+        })
+        .collect::<Option<String>>()?;
+    let fields: Vec<&str> = text.split("..").collect();
+    let (first, last, step) = match fields.as_slice() {
+        [first, last]       => (*first, *last, 1),
+        [first, last, step] => (*first, *last, step.parse::<i64>().ok()?.unsigned_abs().max(1)),
+        _ => return None,
+    };
+    if let (Ok(from), Ok(to)) = (first.parse::<i64>(), last.parse::<i64>()) {
+        let padded = |n: &str| n.trim_start_matches('-').len() > 1 && n.trim_start_matches('-').starts_with('0');
+        let width = match padded(first) || padded(last) {
+            true  => first.len().max(last.len()),
+            false => 0,
+        };
+        let items = steps(from, to, step)?
+            .map(|n| match n < 0 {
+                true  => format!("-{:0>width$}", n.unsigned_abs(), width = width.saturating_sub(1)),
+                false => format!("{:0>width$}", n, width = width),
+            })
+            .collect();
+        return Some(items);
+    }
+    let mut first_chars = first.chars();
+    let mut last_chars = last.chars();
+    match (first_chars.next(), first_chars.next(), last_chars.next(), last_chars.next()) {
+        (Some(from), None, Some(to), None) if from.is_ascii_alphabetic() && to.is_ascii_alphabetic() => {
+            let items = steps(from as i64, to as i64, step)?
+                .filter_map(|c| char::from_u32(c as u32))
+                .map(String::from)
+                .collect();
+            Some(items)
+        },
+        _ => None,
+    }
+}
+
+/// Counts from `from` to `to` inclusive, `step` at a time, down if `to` is
+/// the smaller. A sequence too long to count leaves the word as it is.
+fn steps(from: i64, to: i64, step: u64) -> Option<impl Iterator<Item = i64>> {
+    let count = (from.abs_diff(to) / step).checked_add(1)?;
+    let items = (0..count).map(move |i| {
+        //every item lies between `from` and `to`, so these can't overflow
+        let offset = i * step;
+        match from <= to {
+            true  => from.wrapping_add_unsigned(offset),
+            false => from.wrapping_sub_unsigned(offset),
+        }
+    });
+    Some(items)
+}
+
+/// Puts a word back together from its pieces
+fn into_word(pieces: Vec<Piece>) -> ComplexWordAlias {
+    let mut parts = vec![];
+    let mut literal = String::new();
+    for piece in pieces {
+        match piece {
+            Piece::Char(c) => literal.push(c),
+            Piece::Part(part) => {
+                if !literal.is_empty() {
+                    parts.push(ast::Word::Simple(ast::SimpleWord::Literal(std::mem::take(&mut literal))));
+                }
+                parts.push(part.clone());
+            },
+        }
+    }
+    if !literal.is_empty() {
+        parts.push(ast::Word::Simple(ast::SimpleWord::Literal(literal)));
+    }
+    match parts.len() {
+        1 => ast::ComplexWord::Single(parts.remove(0)),
+        _ => ast::ComplexWord::Concat(parts),
+    }
+}
@@ -0,0 +1,74 @@
+use std::ffi::{OsStr, OsString};
+use std::io::{self, Write};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+
+/// Where each character of a value starts, and where the value ends. A
+/// byte that isn't part of valid UTF-8 counts as a character of its own.
+pub fn boundaries(bytes: &[u8]) -> Vec<usize> {
+    let mut boundaries = vec![];
+    let mut start = 0;
+    for chunk in bytes.utf8_chunks() {
+        boundaries.extend(chunk.valid().char_indices().map(|(i, _)| start + i));
+        start += chunk.valid().len();
+        boundaries.extend(start..start + chunk.invalid().len());
+        start += chunk.invalid().len();
+    }
+    boundaries.push(bytes.len());
+    boundaries
+}
+
+/// Splits a value into the bytes of each of its characters, along with
+/// the character they make, or `None` for a byte that isn't valid UTF-8
+pub fn chars(bytes: &[u8]) -> Vec<(&[u8], Option<char>)> {
+    boundaries(bytes).windows(2)
+        .map(|ends| {
+            let raw = &bytes[ends[0]..ends[1]];
+            (raw, std::str::from_utf8(raw).ok().and_then(|text| text.chars().next()))
+        })
+        .collect()
+}
+
+/// The number of characters in a value, counting as `boundaries` does
+pub fn char_count(bytes: &[u8]) -> usize {
+    boundaries(bytes).len() - 1
+}
+
+/// Joins values with a separator between each pair
+pub fn join(values: &[OsString], separator: &OsStr) -> OsString {
+    let mut joined = Vec::new();
+    for (i, value) in values.iter().enumerate() {
+        if i > 0 {
+            joined.extend_from_slice(separator.as_bytes());
+        }
+        joined.extend_from_slice(value.as_bytes());
+    }
+    OsString::from_vec(joined)
+}
+
+/// Prints a line to stdout exactly as its bytes are
+pub fn println(text: &OsStr) -> io::Result<()> {
+    let mut line = text.as_bytes().to_vec();
+    line.push(b'\n');
+    io::stdout().write_all(&line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_stray_bytes_as_characters() {
+        assert_eq!(boundaries(b"ab"), [0, 1, 2]);
+        assert_eq!(boundaries("\u{e9}x".as_bytes()), [0, 2, 3]);
+        assert_eq!(boundaries(b"\xff\xfea"), [0, 1, 2, 3]);
+        assert_eq!(char_count(b"caf\xc3"), 4);
+        assert_eq!(char_count(b""), 0);
+        assert_eq!(chars(b"a\xff"), [(&b"a"[..], Some('a')), (&b"\xff"[..], None)]);
+    }
+
+    #[test]
+    fn joins_bytes() {
+        let values = [OsString::from_vec(b"a\xff".to_vec()), OsString::from("b")];
+        assert_eq!(join(&values, OsStr::new(":")).as_bytes(), b"a\xff:b");
+    }
+}
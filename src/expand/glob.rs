@@ -0,0 +1,130 @@
+use super::pattern::Pattern;
+use crate::execute::options::Options;
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::OsStrExt;
+use std::fs;
+
+/// Expands a pattern into the paths that match it, sorted. Directories
+/// are searched a path component at a time, and names starting with `.`
+/// are only matched by a pattern that starts with one, unless dotglob is
+/// set. With globstar, a `**` component matches any number of directories.
+/// Names are matched as text but kept as the exact bytes they have.
+pub fn glob(pattern: &str, options: &Options) -> Vec<OsString> {
+    let (mut paths, rest) = match pattern.strip_prefix('/') {
+        Some(rest) => (vec![OsString::from("/")], rest),
+        None       => (vec![OsString::new()], pattern),
+    };
+    let components: Vec<&str> = rest.split('/').collect();
+    let last = components.len() - 1;
+    for (i, component) in components.iter().enumerate() {
+        let dirs_only = i != last;
+        paths = if options.globstar && *component == "**" {
+            paths.iter()
+                .flat_map(|path| descendants(path, dirs_only, options))
+                .collect()
+        }
+        else if is_pattern(component) {
+            let pattern = Pattern::new(component);
+            paths.iter()
+                .flat_map(|path| matching_entries(path, &pattern, component, dirs_only, options))
+                .collect()
+        }
+        else {
+            let name = unescape(component);
+            paths.iter()
+                .map(|path| join(path, OsStr::new(&name)))
+                .filter(|path| fs::symlink_metadata(path).is_ok())
+                .collect()
+        };
+    }
+    paths.sort();
+    paths.dedup();
+    paths
+}
+
+/// Whether a word has unescaped pattern characters, and so is globbed
+pub fn is_pattern(text: &str) -> bool {
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => { chars.next(); },
+            '*' | '?' | '[' => return true,
+            _ => {},
+        }
+    }
+    false
+}
+
+/// The names in the directory at `path` that match a single path component
+fn matching_entries(path: &OsStr, pattern: &Pattern, text: &str, dirs_only: bool, options: &Options) -> Vec<OsString> {
+    let dot_matches = options.dotglob || text.starts_with('.') || text.starts_with("\\.");
+    entries(path, dirs_only)
+        .into_iter()
+        .filter(|name| dot_matches || !name.as_bytes().starts_with(b"."))
+        .filter(|name| pattern.matches(&name.to_string_lossy()))
+        .map(|name| join(path, &name))
+        .collect()
+}
+
+/// What `**` matches under `path`: the directory itself and every
+/// directory below it, or when it is the last component every file and
+/// directory below it. Symbolic links aren't followed.
+fn descendants(path: &OsStr, dirs_only: bool, options: &Options) -> Vec<OsString> {
+    let mut found = vec![];
+    if dirs_only {
+        found.push(path.to_os_string());
+    }
+    let mut pending = vec![path.to_os_string()];
+    while let Some(dir) = pending.pop() {
+        for name in entries(&dir, false) {
+            if name.as_bytes().starts_with(b".") && !options.dotglob {
+                continue;
+            }
+            let child = join(&dir, &name);
+            let is_dir = fs::symlink_metadata(&child).is_ok_and(|metadata| metadata.is_dir());
+            if is_dir {
+                pending.push(child.clone());
+            }
+            if is_dir || !dirs_only {
+                found.push(child);
+            }
+        }
+    }
+    found
+}
+
+/// The names in a directory, or only those of its subdirectories. An
+/// unreadable directory has none.
+fn entries(path: &OsStr, dirs_only: bool) -> Vec<OsString> {
+    let dir = if path.is_empty() { OsStr::new(".") } else { path };
+    let Ok(entries) = fs::read_dir(dir) else {
+        return vec![];
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| !dirs_only || fs::metadata(entry.path()).is_ok_and(|metadata| metadata.is_dir()))
+        .map(|entry| entry.file_name())
+        .collect()
+}
+
+fn join(path: &OsStr, name: &OsStr) -> OsString {
+    let mut joined = path.to_os_string();
+    if !path.is_empty() && !path.as_bytes().ends_with(b"/") {
+        joined.push("/");
+    }
+    joined.push(name);
+    joined
+}
+
+/// Removes the backslashes that escape pattern characters
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unescaped.extend(chars.next()),
+            c    => unescaped.push(c),
+        }
+    }
+    unescaped
+}
@@ -0,0 +1,588 @@
+pub mod pattern;
+pub mod arith;
+pub mod bytes;
+mod brace;
+mod glob;
+
+use crate::command::{generate_command, PROCESS_IN, PROCESS_OUT, TopLevelWordAlias, ComplexWordAlias, WordAlias, SimpleWordAlias, ParameterAlias};
+use crate::execute::{Executor, capture_output, exit_shell, process_substitution, status_code};
+use conch_parser::ast;
+use anyhow::{anyhow, Result};
+use pattern::Pattern;
+use std::mem;
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use nix::unistd::{getuid, User};
+
+/// The field separators used when IFS is unset
+pub const DEFAULT_IFS: &str = " \t\n";
+
+/// A piece of a partially expanded word, remembering where it came from
+/// so that later stages know which characters are still special. What
+/// expansions give is kept as the exact bytes, which needn't be UTF-8.
+#[derive(Debug)]
+enum Segment {
+    /// Unquoted text written on the command line
+    Literal(String),
+    /// Text protected by quotes or a backslash
+    Quoted(OsString),
+    /// The unquoted result of a parameter or command substitution
+    Expanded(OsString),
+    /// The boundary between two positional parameters in `$@`
+    FieldBreak,
+}
+
+impl Segment {
+    fn text(&self) -> &OsStr {
+        match self {
+            Segment::Literal(text) => OsStr::new(text),
+            Segment::Quoted(text) | Segment::Expanded(text) => text,
+            Segment::FieldBreak => OsStr::new(" "),
+        }
+    }
+}
+
+/// Where in a word `~` can start a tilde prefix
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Tildes {
+    /// Only at the start of the word
+    Start,
+    /// At the start, and after the `=` of a word that looks like an
+    /// assignment, as with `export PATH=~/bin`
+    Word,
+    /// At the start and after every `:`, as in the value of an assignment
+    Assignment,
+}
+
+/// A field of a command's arguments before pathname expansion
+#[derive(Debug, Default)]
+struct Field {
+    text: Vec<u8>,
+    /// The text as a pattern, with quoted characters escaped
+    pattern: String,
+}
+
+/// Passes back an expansion error that a script can't go on from, ending
+/// the shell there unless it is interactive
+fn fatal(shell: &mut Executor, error: anyhow::Error) -> anyhow::Error {
+    if !shell.interactive {
+        eprintln!("Execution error: {}", error);
+        exit_shell(shell, 1);
+    }
+    error
+}
+
+/// Expands the words of a command into its argument list. A single word
+/// may produce several arguments, or none at all.
+pub fn expand_words(shell: &mut Executor, words: &[TopLevelWordAlias]) -> Result<Vec<OsString>> {
+    let mut args = vec![];
+    for word in words {
+        let braced = match shell.options.braceexpand {
+            true  => brace::expand(word),
+            false => None,
+        };
+        match braced {
+            Some(braced) => {
+                for word in &braced {
+                    expand_fields(shell, word, &mut args)?;
+                }
+            },
+            None => expand_fields(shell, word, &mut args)?,
+        }
+    }
+    Ok(args)
+}
+
+/// Expands a single word, after brace expansion, into the fields it
+/// makes, adding them to `args`
+fn expand_fields(shell: &mut Executor, word: &ComplexWordAlias, args: &mut Vec<OsString>) -> Result<()> {
+    let mut segments = vec![];
+    expand_complex_word(shell, word, false, Tildes::Word, &mut segments)?;
+    //IFS is read again for each word, as an earlier one may assign it
+    let ifs = shell.variables.get("IFS").map_or(DEFAULT_IFS.into(), OsStr::to_os_string);
+    for field in into_fields(segments, ifs.as_bytes()) {
+        args.extend(expand_pathnames(shell, field)?);
+    }
+    Ok(())
+}
+
+/// Joins segments into fields, breaking them apart where `$@` separated
+/// positional parameters and where unquoted expansions hold characters
+/// from `ifs`. A field made only of empty unquoted expansions is dropped
+/// entirely.
+fn into_fields(segments: Vec<Segment>, ifs: &[u8]) -> Vec<Field> {
+    let separators = bytes::chars(ifs);
+    let mut fields = vec![];
+    let mut field = Field::default();
+    let mut keep = false;
+    for segment in segments {
+        match segment {
+            Segment::FieldBreak => {
+                if keep || !field.text.is_empty() {
+                    fields.push(mem::take(&mut field));
+                }
+                keep = false;
+            },
+            Segment::Quoted(text) => {
+                field.pattern.push_str(&pattern::escape(&text.to_string_lossy()));
+                field.text.extend_from_slice(text.as_bytes());
+                keep = true;
+            },
+            Segment::Literal(text) => {
+                field.pattern.push_str(&text);
+                field.text.extend_from_slice(text.as_bytes());
+                keep = true;
+            },
+            Segment::Expanded(text) => {
+                //whitespace around a delimiter that isn't whitespace is
+                //part of that delimiter, so only ends one field
+                let mut after_whitespace = false;
+                for (raw, c) in bytes::chars(text.as_bytes()) {
+                    if !separators.iter().any(|(separator, _)| *separator == raw) {
+                        field.pattern.push_str(&String::from_utf8_lossy(raw));
+                        field.text.extend_from_slice(raw);
+                        after_whitespace = false;
+                    }
+                    else if matches!(c, Some(' ' | '\t' | '\n')) {
+                        if keep || !field.text.is_empty() {
+                            fields.push(mem::take(&mut field));
+                            keep = false;
+                            after_whitespace = true;
+                        }
+                    }
+                    else {
+                        if !after_whitespace {
+                            fields.push(mem::take(&mut field));
+                            keep = false;
+                        }
+                        after_whitespace = false;
+                    }
+                }
+            },
+        }
+    }
+    if keep || !field.text.is_empty() {
+        fields.push(field);
+    }
+    fields
+}
+
+/// Replaces a field holding unquoted pattern characters with the paths
+/// it matches. When nothing matches the field is kept as it is, unless
+/// nullglob or failglob is set.
+fn expand_pathnames(shell: &mut Executor, field: Field) -> Result<Vec<OsString>> {
+    let text = OsString::from_vec(field.text);
+    if !glob::is_pattern(&field.pattern) {
+        return Ok(vec![text]);
+    }
+    let paths = glob::glob(&field.pattern, &shell.options);
+    if !paths.is_empty() {
+        return Ok(paths);
+    }
+    if shell.options.failglob {
+        return Err(fatal(shell, anyhow!("no match: {}", text.to_string_lossy())));
+    }
+    if shell.options.nullglob {
+        return Ok(vec![]);
+    }
+    Ok(vec![text])
+}
+
+/// Expands a word into a single string, as done for redirection targets
+pub fn expand_word(shell: &mut Executor, word: &TopLevelWordAlias) -> Result<OsString> {
+    let mut segments = vec![];
+    expand_complex_word(shell, word, false, Tildes::Start, &mut segments)?;
+    Ok(concat(&segments))
+}
+
+/// Expands the value of an assignment into a single string, in which a
+/// `~` after any `:` is expanded too, as in `PATH=~/bin:~/.local/bin`
+pub fn expand_assignment(shell: &mut Executor, word: &TopLevelWordAlias) -> Result<OsString> {
+    let mut segments = vec![];
+    expand_complex_word(shell, word, false, Tildes::Assignment, &mut segments)?;
+    Ok(concat(&segments))
+}
+
+fn concat(segments: &[Segment]) -> OsString {
+    let mut text = OsString::new();
+    for segment in segments {
+        text.push(segment.text());
+    }
+    text
+}
+
+/// Expands a word into pattern text in which quoted characters are escaped
+/// and so only match themselves
+pub fn expand_pattern(shell: &mut Executor, word: &TopLevelWordAlias) -> Result<String> {
+    let mut segments = vec![];
+    expand_complex_word(shell, word, false, Tildes::Start, &mut segments)?;
+    let pattern = segments.iter()
+        .map(|segment| match segment {
+            Segment::Quoted(text) => pattern::escape(&text.to_string_lossy()),
+            _ => segment.text().to_string_lossy().into_owned(),
+        })
+        .collect();
+    Ok(pattern)
+}
+
+fn expand_complex_word(shell: &mut Executor, complex_word: &ComplexWordAlias, quoted: bool, mut tildes: Tildes, segments: &mut Vec<Segment>) -> Result<()> {
+    let words = match complex_word {
+        ast::ComplexWord::Concat(word_list) => word_list.as_slice(),
+        ast::ComplexWord::Single(word) => std::slice::from_ref(word),
+    };
+    let mut tilde_allowed = !quoted;
+    let mut i = 0;
+    while i < words.len() {
+        if tilde_allowed && matches!(words[i], ast::Word::Simple(ast::SimpleWord::Tilde)) {
+            if let Some(used) = expand_tilde(shell, &words[i + 1..], tildes, segments) {
+                i += used + 1;
+                tilde_allowed = false;
+                continue;
+            }
+        }
+        if expand_process_substitution(shell, &words[i..], segments)? {
+            i += 2;
+            tilde_allowed = false;
+            continue;
+        }
+        expand_word_part(shell, &words[i], quoted, segments)?;
+        tilde_allowed = !quoted && match &words[i] {
+            ast::Word::Simple(ast::SimpleWord::Colon) => tildes == Tildes::Assignment,
+            ast::Word::Simple(ast::SimpleWord::Literal(lit)) if i == 0 && tildes == Tildes::Word => {
+                if is_assignment(lit) {
+                    tildes = Tildes::Assignment;
+                }
+                tildes == Tildes::Assignment && lit.ends_with('=')
+            },
+            _ => false,
+        };
+        i += 1;
+    }
+    Ok(())
+}
+
+/// Expands a `<(cmd)` or `>(cmd)` at the start of `words`, which the
+/// rewrite pass turned into a literal ending in a marker followed by a
+/// command substitution. Returns whether there was one.
+fn expand_process_substitution(shell: &mut Executor, words: &[WordAlias], segments: &mut Vec<Segment>) -> Result<bool> {
+    let [ast::Word::Simple(ast::SimpleWord::Literal(lit)), ast::Word::Simple(ast::SimpleWord::Subst(subst)), ..] = words else {
+        return Ok(false);
+    };
+    let ast::ParameterSubstitution::Command(commands) = subst.as_ref() else {
+        return Ok(false);
+    };
+    let (prefix, readable) = match (lit.strip_suffix(PROCESS_IN), lit.strip_suffix(PROCESS_OUT)) {
+        (Some(prefix), _) => (prefix, true),
+        (_, Some(prefix)) => (prefix, false),
+        _ => return Ok(false),
+    };
+    if !prefix.is_empty() {
+        segments.push(Segment::Literal(prefix.to_string()));
+    }
+    let mut commands = commands.iter()
+        .map(generate_command)
+        .collect::<Result<Vec<_>>>()?;
+    //the path is used as it is, without splitting or globbing
+    segments.push(Segment::Quoted(process_substitution(shell, &mut commands, readable)?.into()));
+    Ok(true)
+}
+
+/// Whether a word starts with `name=`
+fn is_assignment(text: &str) -> bool {
+    let Some((name, _)) = text.split_once('=') else {
+        return false;
+    };
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Expands the tilde prefix a `~` starts, given the words that follow it.
+/// The prefix runs to the first `/`, or `:` in an assignment, and names
+/// whose home directory to use. Returns how many of the following words
+/// were used, or `None` if the `~` is to be left as it is.
+fn expand_tilde(shell: &Executor, rest: &[WordAlias], tildes: Tildes, segments: &mut Vec<Segment>) -> Option<usize> {
+    let ends_prefix = |word: Option<&WordAlias>| match word {
+        None => true,
+        Some(ast::Word::Simple(ast::SimpleWord::Colon)) => tildes == Tildes::Assignment,
+        _ => false,
+    };
+    let (name, used, remainder) = match rest.first() {
+        word if ends_prefix(word) => ("", 0, None),
+        Some(ast::Word::Simple(ast::SimpleWord::Literal(lit))) => match lit.find('/') {
+            Some(slash) => (&lit[..slash], 1, Some(&lit[slash..])),
+            None if ends_prefix(rest.get(1)) => (lit.as_str(), 1, None),
+            //a prefix with anything quoted or expanded in it isn't expanded
+            None => return None,
+        },
+        _ => return None,
+    };
+    //the directory is used as it is, without splitting or globbing
+    segments.push(Segment::Quoted(tilde_value(shell, name)?));
+    if let Some(remainder) = remainder {
+        segments.push(Segment::Literal(remainder.to_string()));
+    }
+    Some(used)
+}
+
+/// What a tilde prefix stands for: `~` the home directory, `~+` the
+/// current directory, `~-` the previous one and `~user` that user's home
+fn tilde_value(shell: &Executor, name: &str) -> Option<OsString> {
+    let home = |user: Option<User>| user.map(|user| user.dir.into_os_string());
+    match name {
+        "" => shell.variables.get("HOME")
+            .map(OsStr::to_os_string)
+            .or_else(|| home(User::from_uid(getuid()).ok().flatten())),
+        "+" => shell.variables.get("PWD")
+            .map(OsStr::to_os_string)
+            .or_else(|| Some(env::current_dir().ok()?.into_os_string())),
+        "-" => shell.variables.get("OLDPWD").map(OsStr::to_os_string),
+        user => home(User::from_name(user).ok().flatten()),
+    }
+}
+
+fn expand_word_part(shell: &mut Executor, word: &WordAlias, quoted: bool, segments: &mut Vec<Segment>) -> Result<()> {
+    match word {
+        ast::Word::DoubleQuoted(word_list) => {
+            //`""` is still an argument even though nothing is inside it
+            if word_list.is_empty() {
+                segments.push(Segment::Quoted(OsString::new()));
+            }
+            for simple_word in word_list {
+                expand_simple_word(shell, simple_word, true, segments)?;
+            }
+        },
+        ast::Word::SingleQuoted(lit) => segments.push(Segment::Quoted(lit.into())),
+        ast::Word::Simple(simple_word) => expand_simple_word(shell, simple_word, quoted, segments)?,
+    }
+    Ok(())
+}
+
+fn expand_simple_word(shell: &mut Executor, simple_word: &SimpleWordAlias, quoted: bool, segments: &mut Vec<Segment>) -> Result<()> {
+    let literal = |text: &str| if quoted {
+        Segment::Quoted(text.into())
+    }
+    else {
+        Segment::Literal(text.to_string())
+    };
+    match simple_word {
+        ast::SimpleWord::Literal(lit) => segments.push(literal(lit)),
+        ast::SimpleWord::Escaped(esc) => segments.push(Segment::Quoted(esc.into())),
+        ast::SimpleWord::Colon        => segments.push(literal(":")),
+        ast::SimpleWord::Star         => segments.push(literal("*")),
+        ast::SimpleWord::Question     => segments.push(literal("?")),
+        ast::SimpleWord::SquareOpen   => segments.push(literal("[")),
+        ast::SimpleWord::SquareClose  => segments.push(literal("]")),
+        //a tilde that doesn't start a tilde prefix
+        ast::SimpleWord::Tilde        => segments.push(literal("~")),
+        ast::SimpleWord::Param(ast::Parameter::At) => {
+            expand_positional(shell, quoted, segments);
+        },
+        ast::SimpleWord::Param(ast::Parameter::Star) if !quoted => {
+            expand_positional(shell, quoted, segments);
+        },
+        ast::SimpleWord::Param(param) => {
+            let value = parameter_value(shell, param).unwrap_or_default();
+            segments.push(expanded(value, quoted));
+        },
+        ast::SimpleWord::Subst(param_sub) => {
+            expand_substitution(shell, param_sub, quoted, segments)?;
+        },
+    }
+    Ok(())
+}
+
+fn expanded(value: impl Into<OsString>, quoted: bool) -> Segment {
+    let value = value.into();
+    if quoted {
+        Segment::Quoted(value)
+    }
+    else {
+        Segment::Expanded(value)
+    }
+}
+
+/// Expands each positional parameter into a field of its own, as `$@`
+/// does everywhere and `$*` does outside double quotes
+fn expand_positional(shell: &Executor, quoted: bool, segments: &mut Vec<Segment>) {
+    for (i, param) in shell.positional.iter().enumerate() {
+        if i > 0 {
+            segments.push(Segment::FieldBreak);
+        }
+        segments.push(expanded(param.clone(), quoted));
+    }
+}
+
+fn parameter_value(shell: &Executor, parameter: &ast::Parameter<String>) -> Option<OsString> {
+    match parameter {
+        ast::Parameter::Var(name) => shell.variables.get(name).map(OsStr::to_os_string),
+        ast::Parameter::Positional(0) => Some(shell.arg0.clone()),
+        ast::Parameter::Positional(n) => shell.positional.get(*n as usize - 1).cloned(),
+        ast::Parameter::Question => Some(status_code(&shell.last_status).to_string().into()),
+        ast::Parameter::Dollar   => Some(shell.pid.to_string().into()),
+        ast::Parameter::Bang     => shell.last_bg_pid.map(|pid| pid.to_string().into()),
+        ast::Parameter::Pound    => Some(shell.positional.len().to_string().into()),
+        ast::Parameter::Dash     => Some(OsString::new()),
+        ast::Parameter::At       => Some(bytes::join(&shell.positional, OsStr::new(" "))),
+        ast::Parameter::Star     => {
+            //"$*" joins with the first character of IFS, a space if IFS is unset
+            let separator = match shell.variables.get("IFS") {
+                Some(ifs) => bytes::chars(ifs.as_bytes()).first().map_or(OsStr::new(""), |(raw, _)| OsStr::from_bytes(raw)),
+                None      => OsStr::new(" "),
+            };
+            Some(bytes::join(&shell.positional, separator))
+        },
+    }
+}
+
+fn parameter_name(parameter: &ast::Parameter<String>) -> String {
+    match parameter {
+        ast::Parameter::Var(name) => name.clone(),
+        _ => parameter.to_string().trim_start_matches('$').to_string(),
+    }
+}
+
+/// Whether the parameter counts as set for the `${x-...}` family of
+/// substitutions; with a colon an empty value counts as unset too.
+fn is_set(value: &Option<OsString>, colon: bool) -> bool {
+    match value {
+        Some(value) => !(colon && value.is_empty()),
+        None        => false,
+    }
+}
+
+fn expand_substitution(shell: &mut Executor, parameter: &ParameterAlias, quoted: bool, segments: &mut Vec<Segment>) -> Result<()> {
+    match parameter {
+        ast::ParameterSubstitution::Command(commands) => {
+            let mut commands = commands.iter()
+                .map(generate_command)
+                .collect::<Result<Vec<_>>>()?;
+            let mut output = capture_output(shell, &mut commands)?;
+            //only trailing newlines are removed, the rest is kept exactly
+            while output.last() == Some(&b'\n') {
+                output.pop();
+            }
+            segments.push(expanded(OsString::from_vec(output), quoted));
+        },
+        ast::ParameterSubstitution::Len(param) => {
+            let len = match param {
+                ast::Parameter::At | ast::Parameter::Star => shell.positional.len(),
+                _ => bytes::char_count(parameter_value(shell, param).unwrap_or_default().as_bytes()),
+            };
+            segments.push(expanded(len.to_string(), quoted));
+        },
+        ast::ParameterSubstitution::Default(colon, param, word) => {
+            let value = parameter_value(shell, param);
+            if is_set(&value, *colon) {
+                segments.push(expanded(value.unwrap_or_default(), quoted));
+            }
+            else if let Some(word) = word {
+                expand_complex_word(shell, word, quoted, Tildes::Start, segments)?;
+            }
+        },
+        ast::ParameterSubstitution::Assign(colon, param, word) => {
+            let value = parameter_value(shell, param);
+            if is_set(&value, *colon) {
+                segments.push(expanded(value.unwrap_or_default(), quoted));
+            }
+            else {
+                let name = match param {
+                    ast::Parameter::Var(name) => name,
+                    _ => return Err(anyhow!("${}: cannot assign in this way", parameter_name(param))),
+                };
+                let value = match word {
+                    Some(word) => expand_word(shell, word)?,
+                    None       => OsString::new(),
+                };
+                shell.variables.set(name, value.clone());
+                segments.push(expanded(value, quoted));
+            }
+        },
+        ast::ParameterSubstitution::Error(colon, param, word) => {
+            let value = parameter_value(shell, param);
+            if is_set(&value, *colon) {
+                segments.push(expanded(value.unwrap_or_default(), quoted));
+            }
+            else {
+                let message = match word {
+                    Some(word) => expand_word(shell, word)?.to_string_lossy().into_owned(),
+                    None       => String::from("parameter null or not set"),
+                };
+                return Err(fatal(shell, anyhow!("{}: {}", parameter_name(param), message)));
+            }
+        },
+        ast::ParameterSubstitution::Alternative(colon, param, word) => {
+            let value = parameter_value(shell, param);
+            if is_set(&value, *colon) {
+                if let Some(word) = word {
+                    expand_complex_word(shell, word, quoted, Tildes::Start, segments)?;
+                }
+            }
+        },
+        ast::ParameterSubstitution::RemoveSmallestSuffix(param, word) => {
+            let value = parameter_value(shell, param).unwrap_or_default();
+            let pattern = optional_pattern(shell, word)?;
+            segments.push(expanded(remove_suffix(&value, &pattern, false), quoted));
+        },
+        ast::ParameterSubstitution::RemoveLargestSuffix(param, word) => {
+            let value = parameter_value(shell, param).unwrap_or_default();
+            let pattern = optional_pattern(shell, word)?;
+            segments.push(expanded(remove_suffix(&value, &pattern, true), quoted));
+        },
+        ast::ParameterSubstitution::RemoveSmallestPrefix(param, word) => {
+            let value = parameter_value(shell, param).unwrap_or_default();
+            let pattern = optional_pattern(shell, word)?;
+            segments.push(expanded(remove_prefix(&value, &pattern, false), quoted));
+        },
+        ast::ParameterSubstitution::RemoveLargestPrefix(param, word) => {
+            let value = parameter_value(shell, param).unwrap_or_default();
+            let pattern = optional_pattern(shell, word)?;
+            segments.push(expanded(remove_prefix(&value, &pattern, true), quoted));
+        },
+        ast::ParameterSubstitution::Arith(expr) => {
+            let value = match expr {
+                Some(expr) => arith::evaluate(shell, expr).map_err(|e| fatal(shell, e))?,
+                None       => 0,
+            };
+            segments.push(expanded(value.to_string(), quoted));
+        },
+    }
+    Ok(())
+}
+
+fn optional_pattern(shell: &mut Executor, word: &Option<TopLevelWordAlias>) -> Result<Pattern> {
+    let pattern = match word {
+        Some(word) => expand_pattern(shell, word)?,
+        None       => String::new(),
+    };
+    Ok(Pattern::new(&pattern))
+}
+
+fn remove_prefix(value: &OsStr, pattern: &Pattern, largest: bool) -> OsString {
+    let text = value.as_bytes();
+    let mut ends = bytes::boundaries(text);
+    if largest {
+        ends.reverse();
+    }
+    for end in ends {
+        if pattern.matches(&String::from_utf8_lossy(&text[..end])) {
+            return OsStr::from_bytes(&text[end..]).to_os_string();
+        }
+    }
+    value.to_os_string()
+}
+
+fn remove_suffix(value: &OsStr, pattern: &Pattern, largest: bool) -> OsString {
+    let text = value.as_bytes();
+    let mut starts = bytes::boundaries(text);
+    if !largest {
+        starts.reverse();
+    }
+    for start in starts {
+        if pattern.matches(&String::from_utf8_lossy(&text[start..])) {
+            return OsStr::from_bytes(&text[..start]).to_os_string();
+        }
+    }
+    value.to_os_string()
+}
@@ -0,0 +1,225 @@
+/// A compiled shell pattern as used by parameter expansion, `case` and
+/// pathname expansion. Supports `*`, `?`, bracket expressions with ranges,
+/// negation and POSIX character classes, and backslash escapes.
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    tokens: Vec<Token>,
+}
+
+#[derive(Debug, Clone)]
+enum Token {
+    Char(char),
+    AnyChar,
+    AnyString,
+    Bracket { negated: bool, items: Vec<BracketItem> },
+}
+
+#[derive(Debug, Clone)]
+enum BracketItem {
+    Char(char),
+    Range(char, char),
+    Class(CharClass),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum CharClass {
+    Alnum,
+    Alpha,
+    Blank,
+    Cntrl,
+    Digit,
+    Graph,
+    Lower,
+    Print,
+    Punct,
+    Space,
+    Upper,
+    Xdigit,
+}
+
+impl CharClass {
+    fn from_name(name: &str) -> Option<CharClass> {
+        let class = match name {
+            "alnum"  => CharClass::Alnum,
+            "alpha"  => CharClass::Alpha,
+            "blank"  => CharClass::Blank,
+            "cntrl"  => CharClass::Cntrl,
+            "digit"  => CharClass::Digit,
+            "graph"  => CharClass::Graph,
+            "lower"  => CharClass::Lower,
+            "print"  => CharClass::Print,
+            "punct"  => CharClass::Punct,
+            "space"  => CharClass::Space,
+            "upper"  => CharClass::Upper,
+            "xdigit" => CharClass::Xdigit,
+            _ => return None,
+        };
+        Some(class)
+    }
+
+    fn contains(self, c: char) -> bool {
+        match self {
+            CharClass::Alnum  => c.is_alphanumeric(),
+            CharClass::Alpha  => c.is_alphabetic(),
+            CharClass::Blank  => c == ' ' || c == '\t',
+            CharClass::Cntrl  => c.is_control(),
+            CharClass::Digit  => c.is_ascii_digit(),
+            CharClass::Graph  => !c.is_control() && !c.is_whitespace(),
+            CharClass::Lower  => c.is_lowercase(),
+            CharClass::Print  => !c.is_control(),
+            CharClass::Punct  => c.is_ascii_punctuation(),
+            CharClass::Space  => c.is_whitespace(),
+            CharClass::Upper  => c.is_uppercase(),
+            CharClass::Xdigit => c.is_ascii_hexdigit(),
+        }
+    }
+}
+
+impl BracketItem {
+    fn contains(&self, c: char) -> bool {
+        match self {
+            BracketItem::Char(item)        => *item == c,
+            BracketItem::Range(low, high)  => *low <= c && c <= *high,
+            BracketItem::Class(class)      => class.contains(c),
+        }
+    }
+}
+
+impl Token {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Token::Char(expected) => *expected == c,
+            Token::AnyChar        => true,
+            Token::AnyString      => true,
+            Token::Bracket { negated, items } => {
+                items.iter().any(|item| item.contains(c)) != *negated
+            },
+        }
+    }
+}
+
+impl Pattern {
+    pub fn new(pattern: &str) -> Pattern {
+        let chars: Vec<char> = pattern.chars().collect();
+        let mut tokens = vec![];
+        let mut i = 0;
+        while i < chars.len() {
+            match chars[i] {
+                '\\' if i + 1 < chars.len() => {
+                    tokens.push(Token::Char(chars[i + 1]));
+                    i += 2;
+                    continue;
+                },
+                '*' => {
+                    //consecutive stars match the same as one
+                    if !matches!(tokens.last(), Some(Token::AnyString)) {
+                        tokens.push(Token::AnyString);
+                    }
+                },
+                '?' => tokens.push(Token::AnyChar),
+                '[' => {
+                    if let Some((token, next)) = parse_bracket(&chars, i) {
+                        tokens.push(token);
+                        i = next;
+                        continue;
+                    }
+                    tokens.push(Token::Char('['));
+                },
+                c => tokens.push(Token::Char(c)),
+            }
+            i += 1;
+        }
+        Pattern { tokens }
+    }
+
+    /// Whether the whole of `text` matches the pattern
+    pub fn matches(&self, text: &str) -> bool {
+        let chars: Vec<char> = text.chars().collect();
+        let mut t = 0;
+        let mut c = 0;
+        //position of the last `*` seen and the text index it was tried at
+        let mut backtrack: Option<(usize, usize)> = None;
+        while c < chars.len() {
+            match self.tokens.get(t) {
+                Some(Token::AnyString) => {
+                    backtrack = Some((t, c));
+                    t += 1;
+                    continue;
+                },
+                Some(token) if token.matches(chars[c]) => {
+                    t += 1;
+                    c += 1;
+                    continue;
+                },
+                _ => {},
+            }
+            match backtrack {
+                Some((star, tried)) => {
+                    t = star + 1;
+                    c = tried + 1;
+                    backtrack = Some((star, tried + 1));
+                },
+                None => return false,
+            }
+        }
+        self.tokens[t..].iter().all(|token| matches!(token, Token::AnyString))
+    }
+}
+
+/// Parses a bracket expression starting at `chars[start] == '['`, returning
+/// the token and the index just past the closing `]`. Returns `None` if the
+/// bracket is never closed, in which case the `[` is an ordinary character.
+fn parse_bracket(chars: &[char], start: usize) -> Option<(Token, usize)> {
+    let mut i = start + 1;
+    let mut negated = false;
+    if i < chars.len() && (chars[i] == '!' || chars[i] == '^') {
+        negated = true;
+        i += 1;
+    }
+    let mut items = vec![];
+    let mut first = true;
+    loop {
+        let c = *chars.get(i)?;
+        if c == ']' && !first {
+            return Some((Token::Bracket { negated, items }, i + 1));
+        }
+        first = false;
+        if c == '[' && chars.get(i + 1) == Some(&':') {
+            let rest: String = chars[i + 2..].iter().collect();
+            if let Some(end) = rest.find(":]") {
+                if let Some(class) = CharClass::from_name(&rest[..end]) {
+                    items.push(BracketItem::Class(class));
+                    i += 2 + rest[..end].chars().count() + 2;
+                    continue;
+                }
+            }
+        }
+        let low = if c == '\\' {
+            i += 1;
+            *chars.get(i)?
+        }
+        else {
+            c
+        };
+        if chars.get(i + 1) == Some(&'-') && chars.get(i + 2).is_some_and(|high| *high != ']') {
+            items.push(BracketItem::Range(low, chars[i + 2]));
+            i += 3;
+        }
+        else {
+            items.push(BracketItem::Char(low));
+            i += 1;
+        }
+    }
+}
+
+/// Escapes every pattern metacharacter in `text` so that it only matches itself
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '*' | '?' | '[' | ']') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
@@ -2,20 +2,73 @@ pub mod command;
 pub mod builtins;
 pub mod prompt;
 pub mod execute;
-use std::process::ExitStatus;
-use std::os::unix::process::ExitStatusExt;
+pub mod expand;
+use execute::Executor;
+use std::env;
+use std::ffi::OsString;
+use std::path::Path;
 
 fn main() {
-    let mut status = ExitStatus::from_raw(0);
+    let mut shell = Executor::new();
+    if let Err(e) = execute::signals::init() {
+        eprintln!("arsh: couldn't install SIGCHLD handler: {}", e);
+    }
+
+    //`arsh script [args...]` runs a script instead of reading commands interactively
+    let args: Vec<OsString> = env::args_os().collect();
+    if let Some(script) = args.get(1) {
+        shell.arg0 = script.clone();
+        shell.positional = args[2..].to_vec();
+        let code = run_script(&mut shell, Path::new(script));
+        execute::exit_shell(&mut shell, code);
+    }
 
+    shell.interactive = true;
+    execute::jobs::wait_for_foreground();
+    if let Err(e) = execute::signals::init_interactive() {
+        eprintln!("arsh: couldn't set up signal handling: {}", e);
+    }
+    shell.job_control = execute::jobs::init_job_control();
     loop {
-        prompt::print_prompt(&status);
-        let input = prompt::read_from_stdin();
+        execute::traps::run_pending(&mut shell);
+        shell.jobs.reap();
+        shell.jobs.report_changes();
+        prompt::print_prompt(&shell.last_status);
+        let input = match prompt::read_from_stdin() {
+            prompt::Input::Line(input) => input,
+            //Ctrl-C throws away the line being typed
+            prompt::Input::Interrupted => {
+                println!();
+                continue;
+            },
+            prompt::Input::Eof => {
+                let code = execute::status_code(&shell.last_status);
+                execute::exit_shell(&mut shell, code);
+            },
+        };
 
         let commands = command::parse_into_commands(&input);
         match commands {
             Err(e)       => eprintln!("error parsing commands: {}", e),
-            Ok(commands) => status = execute::execute(commands),
+            Ok(commands) => { execute::execute(commands, &mut shell); },
+        }
+    }
+}
+
+fn run_script(shell: &mut Executor, script: &Path) -> i32 {
+    //the parser works on text, so the script has to be read as UTF-8
+    let input = match std::fs::read(script) {
+        Ok(input) => String::from_utf8_lossy(&input).into_owned(),
+        Err(e)    => {
+            eprintln!("arsh: {}: {}", script.display(), e);
+            return 127;
         }
+    };
+    match command::parse_into_commands(&input) {
+        Err(e)       => {
+            eprintln!("error parsing commands: {}", e);
+            2
+        },
+        Ok(commands) => execute::status_code(&execute::execute(commands, shell)),
     }
 }
@@ -2,6 +2,9 @@ use std::env;
 use std::path::PathBuf;
 use std::process::ExitStatus;
 use std::io::{self, Write};
+use nix::errno::Errno;
+use crate::execute::signals;
+use crate::command::{is_incomplete, scan};
 
 pub fn print_prompt(exit_status: &ExitStatus) {
     let path = match env::current_dir() {
@@ -37,15 +40,40 @@ pub fn print_cont_prompt() {
     }
 }
 
-pub fn read_from_stdin() -> String {
+pub enum Input {
+    Line(String),
+    /// The user pressed Ctrl-C, discarding what was typed
+    Interrupted,
+    /// End of input, which ends the shell
+    Eof,
+}
+
+/// Reads a command, prompting for more lines while it is incomplete
+pub fn read_from_stdin() -> Input {
     let mut input = String::new();
+    signals::take_interrupt();
     loop {
-        match io::stdin().read_line(&mut input) {
+        //the bodies of any here-documents follow the command
+        let heredoc = !scan(&input).unfinished.is_empty();
+        match read_line(&mut input) {
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => return Input::Interrupted,
             Err(err) => eprintln!("Prompt error: {}", err),
-            Ok(0) => std::process::exit(0),     //EOF, end shell
+            Ok(0) if heredoc => break,          //EOF ends the here-document
+            Ok(0) => return Input::Eof,
             Ok(_) => {},                        //bytes read successfully
         }
-        if input.ends_with("&&\n") || input.ends_with("||\n")
+        if !scan(&input).unfinished.is_empty() {
+            print_cont_prompt();
+        }
+        else if heredoc {
+            //the line ending the here-document ends the command unless
+            //it is still incomplete
+            if !is_incomplete(&input) {
+                break;
+            }
+            print_cont_prompt();
+        }
+        else if input.ends_with("&&\n") || input.ends_with("||\n")
             || input.ends_with("|\n") || input.ends_with(">\n")
             || input.ends_with(">>\n") || input.ends_with("<\n") {
             input.pop();   //remove newline char to concat input across lines
@@ -56,9 +84,39 @@ pub fn read_from_stdin() -> String {
             input.pop();   //remove newline char to concat input across lines
             input.pop();   //don't escape first char of next line
         }
+        else if is_incomplete(&input) {
+            print_cont_prompt();
+        }
         else {
             break;
         }
     }
-    input
+    Input::Line(input)
+}
+
+/// Reads one line from stdin a byte at a time, so nothing meant for the
+/// commands it runs is consumed. Unlike `Stdin::read_line` it gives up
+/// with `Interrupted` when SIGINT arrives.
+fn read_line(input: &mut String) -> io::Result<usize> {
+    let mut line = vec![];
+    let mut byte = [0];
+    loop {
+        match nix::unistd::read(0, &mut byte) {
+            Ok(0) => break,
+            Ok(_) => {
+                line.push(byte[0]);
+                if byte[0] == b'\n' {
+                    break;
+                }
+            },
+            Err(Errno::EINTR) => {
+                if signals::take_interrupt() {
+                    return Err(io::ErrorKind::Interrupted.into());
+                }
+            },
+            Err(e) => return Err(e.into()),
+        }
+    }
+    input.push_str(&String::from_utf8_lossy(&line));
+    Ok(line.len())
 }
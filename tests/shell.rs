@@ -0,0 +1,49 @@
+use std::env;
+use std::fs;
+use std::process::Command;
+
+/// Runs `script` with arsh, returning the bytes it printed to stdout
+fn run_bytes(name: &str, script: &str) -> Vec<u8> {
+    let path = env::temp_dir().join(format!("arsh-test-{}-{}.sh", name, std::process::id()));
+    fs::write(&path, script).expect("couldn't write the test script");
+    let output = Command::new(env!("CARGO_BIN_EXE_arsh")).arg(&path).output().expect("couldn't run arsh");
+    fs::remove_file(&path).ok();
+    output.stdout
+}
+
+/// Runs `script` with arsh, returning what it printed to stdout
+fn run(name: &str, script: &str) -> String {
+    String::from_utf8_lossy(&run_bytes(name, script)).into_owned()
+}
+
+#[test]
+fn wait_without_operands_succeeds() {
+    assert_eq!(run("wait-all", "false & wait; echo $?\n"), "0\n");
+    assert_eq!(run("wait-job", "false & wait %1; echo $?\n"), "1\n");
+}
+
+#[test]
+fn ifs_can_be_saved_and_restored() {
+    let script = "old=$IFS; IFS=:; set -- $(echo a:b); echo $#; IFS=$old; set -- $(echo 'c d'); echo $#\n";
+    assert_eq!(run("ifs", script), "2\n2\n");
+}
+
+#[test]
+fn failglob_ends_a_script() {
+    assert_eq!(run("failglob", "shopt -s failglob\necho /no-such-dir/*\necho after\n"), "");
+}
+
+#[test]
+fn arithmetic_commands() {
+    assert_eq!(run("arith-quotes", "x=2\n(( \"1\" + x == 3 )) && echo yes\n"), "yes\n");
+    assert_eq!(run("arith-empty", "(( )); echo $?\n"), "1\n");
+    assert_eq!(run("arith-error", "echo $((1/0))\necho after\n"), "");
+}
+
+#[test]
+fn bytes_reach_commands_exactly() {
+    let script = "x=$(printf 'a\\377b\\n\\n')\nprintf '%s|' \"$x\" ${#x} \"${x#a}\"\n";
+    assert_eq!(run_bytes("bytes", script), b"a\xffb|3|\xffb|");
+    let script = "IFS=$(printf '\\377')\nset -- $(printf 'a\\377b')\necho $#\n";
+    assert_eq!(run("bytes-ifs", script), "2\n");
+}